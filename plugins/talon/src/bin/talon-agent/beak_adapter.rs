@@ -11,15 +11,16 @@ use serde_json::Value as Json;
 /// Beak-compatible trace structure.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BeakTrace {
-    /// Shortened trace identifier (first 8 characters).
+    /// Per-event identifier: the event's span ID, or the first 8 characters of
+    /// its trace ID when it has none.
     pub id: String,
 
     pub timestamp: String,
 
-    /// Collection name (defaults to "claude-code").
+    /// Collection name (`exporters.beak.collection`, default "claude-code").
     pub collection: String,
 
-    /// Flow identifier (defaults to "conversations").
+    /// Flow identifier (`exporters.beak.flow`, default "conversations").
     pub flow: String,
 
     /// Input data including model context, session, and messages.
@@ -46,30 +47,58 @@ pub struct Label {
     pub value: String,
 }
 
+/// Where exported rows are filed in Beak, set in the `[exporters.beak]` config table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BeakOptions {
+    pub collection: String,
+    pub flow: String,
+}
+
+impl Default for BeakOptions {
+    fn default() -> Self {
+        Self {
+            collection: "claude-code".to_string(),
+            flow: "conversations".to_string(),
+        }
+    }
+}
+
 /// Transforms a TraceV1 event into Beak-compatible format.
 ///
 /// Key transformations:
-/// - Shortens trace_id to first 8 characters
+/// - Uses the span ID as the row `id`, since every event of a session shares its
+///   trace ID; trace and span IDs also go in `inputs` so the span tree can be
+///   rebuilt
 /// - Moves token metrics from `trace.metrics` into `outputs` (required by Beak UI)
 /// - Constructs inputs, outputs, and configuration JSONB objects
-pub fn to_beak_format(trace: &TraceV1) -> BeakTrace {
-    let id = if trace.ids.trace_id.len() >= 8 {
+/// - Passes session summaries through as `outputs.session_summary`
+pub fn to_beak_format(trace: &TraceV1, options: &BeakOptions) -> BeakTrace {
+    let id = if !trace.ids.span_id.is_empty() {
+        trace.ids.span_id.clone()
+    } else if trace.ids.trace_id.len() >= 8 {
         trace.ids.trace_id[..8].to_string()
     } else {
         trace.ids.trace_id.clone()
     };
 
     let inputs = serde_json::json!({
+        "event": trace.event,
         "model": trace.configuration.model,
         "session_id": trace.ids.session_id,
         "conversation_id": trace.ids.conversation_id,
+        "message_id": trace.ids.message_id,
+        "trace_id": trace.ids.trace_id,
+        "span_id": trace.ids.span_id,
+        "parent_span_id": trace.ids.parent_span_id,
         "tool_name": trace.inputs.tool.name,
         "tool_version": trace.inputs.tool.version,
         "tool_args": trace.inputs.tool.args,
         "messages": trace.inputs.messages_compact.iter().map(|msg| {
             serde_json::json!({
                 "role": msg.role,
-                "content": msg.content
+                "content": msg.content,
+                "chars": msg.chars,
+                "truncated": msg.truncated
             })
         }).collect::<Vec<_>>(),
         "retrieval_items": trace.inputs.retrieval_items.iter().map(|item| {
@@ -82,7 +111,7 @@ pub fn to_beak_format(trace: &TraceV1) -> BeakTrace {
         }).collect::<Vec<_>>()
     });
 
-    let outputs = serde_json::json!({
+    let mut outputs = serde_json::json!({
         "response": trace.outputs.assistant_text,
         "finish_reason": trace.outputs.finish_reason,
         "truncated": trace.outputs.truncated,
@@ -96,6 +125,9 @@ pub fn to_beak_format(trace: &TraceV1) -> BeakTrace {
         "input_tokens": trace.metrics.prompt_tokens,
         "output_tokens": trace.metrics.completion_tokens,
         "total_tokens": trace.metrics.total_tokens,
+        "uncached_input_tokens": trace.metrics.uncached_input_tokens,
+        "cache_creation_input_tokens": trace.metrics.cache_creation_input_tokens,
+        "cache_read_input_tokens": trace.metrics.cache_read_input_tokens,
         "tokens_estimated": trace.metrics.token_counts_estimated,
         "latency_ms": {
             "first_token": trace.metrics.latency_ms.first_token,
@@ -106,8 +138,15 @@ pub fn to_beak_format(trace: &TraceV1) -> BeakTrace {
         "input_cost_usd": trace.metrics.input_cost_usd,
         "output_cost_usd": trace.metrics.output_cost_usd,
         "total_cost_usd": trace.metrics.total_cost_usd,
-        "quality_score": trace.metrics.quality_score
+        "quality_score": trace.metrics.quality_score,
+        "lines_added": trace.metrics.lines_added,
+        "lines_removed": trace.metrics.lines_removed,
+        "bytes_added": trace.metrics.bytes_added,
+        "bytes_removed": trace.metrics.bytes_removed
     });
+    if let Some(summary) = trace.extensions.get("session_summary") {
+        outputs["session_summary"] = summary.clone();
+    }
 
     let configuration = serde_json::json!({
         "model": trace.configuration.model,
@@ -131,13 +170,13 @@ pub fn to_beak_format(trace: &TraceV1) -> BeakTrace {
     BeakTrace {
         id,
         timestamp: trace.timestamp.clone(),
-        collection: "claude-code".to_string(),
-        flow: "conversations".to_string(),
+        collection: options.collection.clone(),
+        flow: options.flow.clone(),
         inputs,
         outputs,
         configuration,
         labels,
-        files: Vec::new(),
+        files: trace.inputs.files.clone(),
     }
 }
 
@@ -156,7 +195,7 @@ mod tests {
         trace.metrics.completion_tokens = 150;
         trace.metrics.total_tokens = 1150;
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         assert_eq!(beak.id, "12345678");
         assert_eq!(beak.timestamp, "2025-11-13T10:30:00Z");
@@ -172,7 +211,7 @@ mod tests {
         trace.metrics.total_tokens = 1150;
         trace.metrics.token_counts_estimated = true;
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         // Verify token metrics are in outputs
         assert_eq!(
@@ -195,6 +234,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_cache_token_breakdown_in_outputs() {
+        let mut trace = TraceV1::default();
+        trace.metrics.prompt_tokens = 3500;
+        trace.metrics.uncached_input_tokens = 1000;
+        trace.metrics.cache_creation_input_tokens = 500;
+        trace.metrics.cache_read_input_tokens = 2000;
+
+        let beak = to_beak_format(&trace, &BeakOptions::default());
+        for (key, want) in [
+            ("input_tokens", 3500),
+            ("uncached_input_tokens", 1000),
+            ("cache_creation_input_tokens", 500),
+            ("cache_read_input_tokens", 2000),
+        ] {
+            assert_eq!(
+                beak.outputs.get(key).and_then(|v| v.as_u64()),
+                Some(want),
+                "{key}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_diff_stats_in_outputs() {
+        let mut trace = TraceV1::default();
+        trace.metrics.lines_added = 12;
+        trace.metrics.lines_removed = 3;
+        trace.metrics.bytes_added = 400;
+        trace.metrics.bytes_removed = 90;
+
+        let beak = to_beak_format(&trace, &BeakOptions::default());
+        for (key, want) in [
+            ("lines_added", 12),
+            ("lines_removed", 3),
+            ("bytes_added", 400),
+            ("bytes_removed", 90),
+        ] {
+            assert_eq!(
+                beak.outputs.get(key).and_then(|v| v.as_u64()),
+                Some(want),
+                "{key}"
+            );
+        }
+    }
+
     #[test]
     fn test_configuration_mapping() {
         let mut trace = TraceV1::default();
@@ -205,7 +290,7 @@ mod tests {
         trace.configuration.max_tokens = 4096;
         trace.configuration.seed = 42;
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         assert_eq!(
             beak.configuration.get("model").and_then(|v| v.as_str()),
@@ -254,9 +339,10 @@ mod tests {
         trace.inputs.messages_compact.push(Message {
             role: "user".to_string(),
             content: "Hello".to_string(),
+            ..Default::default()
         });
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         assert_eq!(
             beak.inputs.get("model").and_then(|v| v.as_str()),
@@ -291,7 +377,7 @@ mod tests {
         trace.metrics.output_cost_usd = 0.02;
         trace.metrics.total_cost_usd = 0.03;
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         assert_eq!(
             beak.outputs.get("response").and_then(|v| v.as_str()),
@@ -334,7 +420,7 @@ mod tests {
             value: "ml-team".to_string(),
         });
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         assert_eq!(beak.labels.len(), 2);
         assert_eq!(beak.labels[0].key, "environment");
@@ -348,15 +434,72 @@ mod tests {
         let mut trace = TraceV1::default();
         trace.ids.trace_id = "short".to_string();
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         assert_eq!(beak.id, "short");
     }
 
+    #[test]
+    fn test_events_of_a_session_get_distinct_ids() {
+        let event = |name: &str| {
+            let mut t = TraceV1 {
+                event: name.to_string(),
+                ..Default::default()
+            };
+            t.ids.session_id = "sess-1".to_string();
+            canonicalize(&mut t);
+            t
+        };
+        let (a, b) = (event("tool.pre"), event("tool.post"));
+        assert_eq!(a.ids.trace_id, b.ids.trace_id);
+
+        let options = BeakOptions::default();
+        let (a, b) = (to_beak_format(&a, &options), to_beak_format(&b, &options));
+        assert_ne!(a.id, b.id);
+        assert_eq!(a.id, a.inputs["span_id"]);
+    }
+
+    #[test]
+    fn test_span_ids_in_inputs() {
+        let mut trace = TraceV1::default();
+        trace.ids.trace_id = "12345678-aaaa".to_string();
+        trace.ids.span_id = "span-1".to_string();
+        trace.ids.parent_span_id = "root".to_string();
+
+        let beak = to_beak_format(&trace, &BeakOptions::default());
+
+        assert_eq!(beak.inputs["trace_id"], "12345678-aaaa");
+        assert_eq!(beak.inputs["span_id"], "span-1");
+        assert_eq!(beak.inputs["parent_span_id"], "root");
+    }
+
+    #[test]
+    fn test_files_mapping() {
+        let mut trace = TraceV1::default();
+        trace.inputs.files = vec!["src/main.rs".to_string()];
+        let beak = to_beak_format(&trace, &BeakOptions::default());
+        assert_eq!(beak.files, ["src/main.rs"]);
+    }
+
+    #[test]
+    fn test_session_summary_in_outputs() {
+        let mut trace = TraceV1 {
+            event: "session.summary".to_string(),
+            ..Default::default()
+        };
+        trace.extensions = serde_json::json!({ "session_summary": { "turns": 3 } });
+        let beak = to_beak_format(&trace, &BeakOptions::default());
+        assert_eq!(beak.inputs["event"], "session.summary");
+        assert_eq!(beak.outputs["session_summary"]["turns"], 3);
+
+        let plain = to_beak_format(&TraceV1::default(), &BeakOptions::default());
+        assert!(plain.outputs.get("session_summary").is_none());
+    }
+
     #[test]
     fn test_empty_trace() {
         let trace = TraceV1::default();
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         assert_eq!(beak.id, "");
         assert_eq!(beak.collection, "claude-code");
@@ -364,6 +507,17 @@ mod tests {
         assert!(beak.files.is_empty());
     }
 
+    #[test]
+    fn test_collection_and_flow_come_from_options() {
+        let options = BeakOptions {
+            collection: "ci".to_string(),
+            flow: "nightly".to_string(),
+        };
+        let beak = to_beak_format(&TraceV1::default(), &options);
+        assert_eq!(beak.collection, "ci");
+        assert_eq!(beak.flow, "nightly");
+    }
+
     #[test]
     fn test_latency_metrics_in_outputs() {
         let mut trace = TraceV1::default();
@@ -372,7 +526,7 @@ mod tests {
         trace.metrics.latency_ms.total = 1000;
         trace.metrics.latency_estimated = true;
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         let latency = beak.outputs.get("latency_ms").expect("latency_ms exists");
         assert_eq!(
@@ -397,7 +551,7 @@ mod tests {
         trace.metrics.total_cost_usd = 0.020;
         trace.metrics.quality_score = 0.95;
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         // Use approximate comparisons for f32 values
         let input_cost = beak
@@ -438,7 +592,7 @@ mod tests {
             status: "success".to_string(),
         });
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         let tool_calls = beak
             .outputs
@@ -466,7 +620,7 @@ mod tests {
             size_tokens: 500,
         });
 
-        let beak = to_beak_format(&trace);
+        let beak = to_beak_format(&trace, &BeakOptions::default());
 
         let items = beak
             .inputs
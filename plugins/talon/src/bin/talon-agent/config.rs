@@ -0,0 +1,719 @@
+//! Agent configuration file and settings resolution.
+//!
+//! Settings come from four layers, highest precedence first:
+//! 1. Command-line flags
+//! 2. Environment variables (`TRACE_ENDPOINT`, `TALON_BATCH_SIZE`, ...)
+//! 3. The TOML config file (`--config`, `TALON_CONFIG`, or `<config dir>/talon/config.toml`)
+//! 4. Built-in defaults
+//!
+//! Clap already merges (1) and (2), so this module only layers the file and the
+//! defaults underneath. Because auto-started agents read the same default file and
+//! inherit the tap's environment, every setting but `sock` can be configured for
+//! them too; the tap passes its own `TALON_SOCK` path as `--sock`.
+//!
+//! # File format
+//! ```toml
+//! endpoint = "https://collector.example.com/v1/traces"
+//! batch_size = 100
+//! profile = "dev"              # optional default profile
+//!
+//! [profiles.dev]
+//! endpoint = "http://localhost:3000/v1/traces"
+//! batch_ms = 50
+//!
+//! [pricing."claude-sonnet-4-5"]   # see `pricing` for the format
+//! input = 3.0
+//! output = 15.0
+//!
+//! [exporters.beak]
+//! collection = "claude-code"
+//! flow = "conversations"
+//!
+//! [processors]                    # see `processors`
+//! git = true
+//! drop_events = ["tool.pre"]
+//! drop_labels = ["git_remote"]
+//! ```
+//!
+//! A profile's keys override the top-level keys; `pricing` entries are merged per
+//! model, and `exporters` and `processors` per key. Unknown keys are rejected so typos
+//! don't silently fall back to defaults.
+
+use crate::Config;
+use crate::beak_adapter::BeakOptions;
+use crate::map::{CaptureMode, MapOptions};
+use crate::pricing::{ModelPrice, PriceTable};
+use crate::processors::Processors;
+use crate::secret::{ApiKey, KeySource};
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// A partial set of agent settings. `None` means "not set at this layer".
+///
+/// Field names double as config file keys.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Event socket path. The control socket lives at `<sock>.ctl`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sock: Option<String>,
+
+    /// Trace collector HTTP endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+
+    /// Bearer token for the collector.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+
+    /// File containing the bearer token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_file: Option<PathBuf>,
+
+    /// Credential helper command that prints the bearer token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_cmd: Option<String>,
+
+    /// Seconds before a key read from a file or helper is fetched again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_refresh_secs: Option<u64>,
+
+    /// Max events per batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_size: Option<usize>,
+
+    /// Max milliseconds before a partial batch is flushed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_ms: Option<u64>,
+
+    /// Capacity of the channel between listener and batching loop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chan_capacity: Option<usize>,
+
+    /// Max serialized bytes per batch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_bytes: Option<usize>,
+
+    /// Spool file size that triggers rotation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spool_bytes: Option<u64>,
+
+    /// Spool directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spool_dir: Option<PathBuf>,
+
+    /// Seconds a tool start waits for its end before it is sent as incomplete.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span_timeout_secs: Option<u64>,
+
+    /// How much of each turn's conversation is copied into `agent.stop` events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_messages: Option<CaptureMode>,
+
+    /// Characters kept per captured message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_max_chars: Option<usize>,
+
+    /// Model price overrides on top of the built-in table, keyed by model ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<BTreeMap<String, ModelPrice>>,
+
+    /// Per-exporter options.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exporters: Option<ExporterSettings>,
+
+    /// Processing applied to events before they are sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processors: Option<ProcessorSettings>,
+}
+
+/// The `[exporters]` table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExporterSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beak: Option<BeakSettings>,
+}
+
+/// The `[exporters.beak]` table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BeakSettings {
+    /// Beak collection rows are written to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+
+    /// Beak flow within the collection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow: Option<String>,
+}
+
+/// The `[processors]` table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessorSettings {
+    /// Label events with the git context of their working directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<bool>,
+
+    /// Canonical event names that are never sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drop_events: Option<Vec<String>>,
+
+    /// Label keys removed before events are sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drop_labels: Option<Vec<String>>,
+}
+
+impl ExporterSettings {
+    fn overlay(self, higher: ExporterSettings) -> ExporterSettings {
+        ExporterSettings {
+            beak: match (self.beak, higher.beak) {
+                (Some(lower), Some(higher)) => Some(BeakSettings {
+                    collection: higher.collection.or(lower.collection),
+                    flow: higher.flow.or(lower.flow),
+                }),
+                (lower, higher) => higher.or(lower),
+            },
+        }
+    }
+}
+
+impl ProcessorSettings {
+    fn overlay(self, higher: ProcessorSettings) -> ProcessorSettings {
+        ProcessorSettings {
+            git: higher.git.or(self.git),
+            drop_events: higher.drop_events.or(self.drop_events),
+            drop_labels: higher.drop_labels.or(self.drop_labels),
+        }
+    }
+}
+
+/// Merges optional tables key by key, `higher` winning.
+fn overlay_table<T>(
+    lower: Option<T>,
+    higher: Option<T>,
+    merge: impl FnOnce(T, T) -> T,
+) -> Option<T> {
+    match (lower, higher) {
+        (Some(lower), Some(higher)) => Some(merge(lower, higher)),
+        (lower, higher) => higher.or(lower),
+    }
+}
+
+impl Settings {
+    /// Built-in defaults for everything except `endpoint` and the API key source.
+    pub fn defaults() -> Result<Self> {
+        Ok(Self {
+            sock: Some("/tmp/talon.sock".to_string()),
+            endpoint: None,
+            api_key: None,
+            api_key_file: None,
+            api_key_cmd: None,
+            api_key_refresh_secs: Some(300),
+            batch_size: Some(100),
+            batch_ms: Some(200),
+            chan_capacity: Some(10_000),
+            batch_bytes: Some(1_048_576),
+            spool_bytes: Some(50_000_000),
+            spool_dir: Some(crate::default_spool_dir()?),
+            span_timeout_secs: Some(900),
+            capture_messages: Some(CaptureMode::Off),
+            capture_max_chars: Some(MapOptions::default().max_chars),
+            pricing: None,
+            exporters: Some(ExporterSettings {
+                beak: Some(BeakSettings {
+                    collection: Some(BeakOptions::default().collection),
+                    flow: Some(BeakOptions::default().flow),
+                }),
+            }),
+            processors: Some(ProcessorSettings {
+                git: Some(true),
+                drop_events: Some(Vec::new()),
+                drop_labels: Some(Vec::new()),
+            }),
+        })
+    }
+
+    /// Returns `self` with every field that `higher` sets replaced by `higher`'s value.
+    ///
+    /// `api_key`, `api_key_file` and `api_key_cmd` are one setting: if `higher`
+    /// sets any of them, all three come from `higher`. `pricing` is merged per
+    /// model and `exporters` and `processors` per key, `higher` winning.
+    pub fn overlay(self, higher: Settings) -> Settings {
+        let key_layer = if higher.has_key_source() {
+            &higher
+        } else {
+            &self
+        };
+        let (api_key, api_key_file, api_key_cmd) = (
+            key_layer.api_key.clone(),
+            key_layer.api_key_file.clone(),
+            key_layer.api_key_cmd.clone(),
+        );
+        let pricing = overlay_table(self.pricing, higher.pricing, |mut lower, higher| {
+            lower.extend(higher);
+            lower
+        });
+        let exporters = overlay_table(self.exporters, higher.exporters, ExporterSettings::overlay);
+        let processors = overlay_table(
+            self.processors,
+            higher.processors,
+            ProcessorSettings::overlay,
+        );
+        Settings {
+            sock: higher.sock.or(self.sock),
+            endpoint: higher.endpoint.or(self.endpoint),
+            api_key,
+            api_key_file,
+            api_key_cmd,
+            api_key_refresh_secs: higher.api_key_refresh_secs.or(self.api_key_refresh_secs),
+            batch_size: higher.batch_size.or(self.batch_size),
+            batch_ms: higher.batch_ms.or(self.batch_ms),
+            chan_capacity: higher.chan_capacity.or(self.chan_capacity),
+            batch_bytes: higher.batch_bytes.or(self.batch_bytes),
+            spool_bytes: higher.spool_bytes.or(self.spool_bytes),
+            spool_dir: higher.spool_dir.or(self.spool_dir),
+            span_timeout_secs: higher.span_timeout_secs.or(self.span_timeout_secs),
+            capture_messages: higher.capture_messages.or(self.capture_messages),
+            capture_max_chars: higher.capture_max_chars.or(self.capture_max_chars),
+            pricing,
+            exporters,
+            processors,
+        }
+    }
+
+    fn has_key_source(&self) -> bool {
+        self.api_key.is_some() || self.api_key_file.is_some() || self.api_key_cmd.is_some()
+    }
+
+    /// Converts fully merged settings into the runtime configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no endpoint was configured at any layer, if more than
+    /// one API key source is set, if a batching limit is zero, if a price is
+    /// negative, or if the Beak collection or flow is empty.
+    pub fn into_config(self) -> Result<Config> {
+        let endpoint = self.endpoint.filter(|e| !e.is_empty()).ok_or_else(|| {
+            anyhow!("no endpoint configured (use --endpoint, TRACE_ENDPOINT or the config file)")
+        })?;
+        let defaults = Settings::defaults()?;
+        let source = match (self.api_key, self.api_key_file, self.api_key_cmd) {
+            (None, None, None) => None,
+            (Some(key), None, None) => Some(KeySource::Value(key)),
+            (None, Some(path), None) => Some(KeySource::File(path)),
+            (None, None, Some(cmd)) => Some(KeySource::Command(cmd)),
+            _ => {
+                return Err(anyhow!(
+                    "set only one of api_key, api_key_file and api_key_cmd"
+                ));
+            }
+        };
+        let beak = self.exporters.and_then(|e| e.beak).unwrap_or_default();
+        let default_beak = BeakOptions::default();
+        let processors = self.processors.unwrap_or_default();
+        let refresh = Duration::from_secs(
+            self.api_key_refresh_secs
+                .or(defaults.api_key_refresh_secs)
+                .unwrap_or_default(),
+        );
+        let config = Config {
+            sock: self.sock.or(defaults.sock).unwrap_or_default(),
+            endpoint,
+            api_key: source.map(|s| ApiKey::new(s, refresh)),
+            batch_size: self.batch_size.or(defaults.batch_size).unwrap_or_default(),
+            batch_ms: self.batch_ms.or(defaults.batch_ms).unwrap_or_default(),
+            chan_capacity: self
+                .chan_capacity
+                .or(defaults.chan_capacity)
+                .unwrap_or_default(),
+            batch_bytes: self
+                .batch_bytes
+                .or(defaults.batch_bytes)
+                .unwrap_or_default(),
+            spool_dir: self.spool_dir.or(defaults.spool_dir).unwrap_or_default(),
+            spool_bytes: self
+                .spool_bytes
+                .or(defaults.spool_bytes)
+                .unwrap_or_default(),
+            span_timeout_secs: self
+                .span_timeout_secs
+                .or(defaults.span_timeout_secs)
+                .unwrap_or_default(),
+            map: MapOptions {
+                capture: self
+                    .capture_messages
+                    .or(defaults.capture_messages)
+                    .unwrap_or_default(),
+                max_chars: self
+                    .capture_max_chars
+                    .or(defaults.capture_max_chars)
+                    .unwrap_or_default(),
+                git: processors.git.unwrap_or(true),
+            },
+            pricing: PriceTable::with_overrides(self.pricing.unwrap_or_default()),
+            beak: BeakOptions {
+                collection: beak.collection.unwrap_or(default_beak.collection),
+                flow: beak.flow.unwrap_or(default_beak.flow),
+            },
+            processors: Processors {
+                drop_events: processors
+                    .drop_events
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect(),
+                drop_labels: processors
+                    .drop_labels
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect(),
+            },
+        };
+        config.pricing.validate()?;
+        if config.batch_size == 0 || config.chan_capacity == 0 || config.batch_bytes == 0 {
+            return Err(anyhow!(
+                "batch_size, chan_capacity and batch_bytes must be greater than zero"
+            ));
+        }
+        if config.beak.collection.is_empty() || config.beak.flow.is_empty() {
+            return Err(anyhow!(
+                "exporters.beak collection and flow must not be empty"
+            ));
+        }
+        Ok(config)
+    }
+
+    /// Copy safe to print: secrets are replaced with a placeholder.
+    pub fn redacted(&self) -> Settings {
+        let mut s = self.clone();
+        if s.api_key.is_some() {
+            s.api_key = Some("<redacted>".to_string());
+        }
+        s
+    }
+}
+
+/// Parsed config file: base settings plus named profiles.
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigFile {
+    /// Profile to apply when none is requested explicitly.
+    pub default_profile: Option<String>,
+    pub base: Settings,
+    pub profiles: BTreeMap<String, Settings>,
+}
+
+impl ConfigFile {
+    /// Parses config file contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error on invalid TOML, unknown keys, or wrongly typed values.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(text)?;
+
+        let default_profile = match table.remove("profile") {
+            Some(toml::Value::String(name)) => Some(name),
+            Some(other) => return Err(anyhow!("`profile` must be a string, got {other}")),
+            None => None,
+        };
+        let profiles = match table.remove("profiles") {
+            Some(v) => v
+                .try_into::<BTreeMap<String, Settings>>()
+                .context("invalid [profiles] table")?,
+            None => BTreeMap::new(),
+        };
+        let base = toml::Value::Table(table).try_into::<Settings>()?;
+
+        Ok(Self {
+            default_profile,
+            base,
+            profiles,
+        })
+    }
+
+    /// Settings for the requested profile (or the file's default profile).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the profile is not defined in the file.
+    pub fn settings(&self, profile: Option<&str>) -> Result<Settings> {
+        let Some(name) = profile.or(self.default_profile.as_deref()) else {
+            return Ok(self.base.clone());
+        };
+        let overrides = self.profiles.get(name).ok_or_else(|| {
+            let known: Vec<_> = self.profiles.keys().map(String::as_str).collect();
+            anyhow!("unknown profile `{name}` (defined: {})", known.join(", "))
+        })?;
+        Ok(self.base.clone().overlay(overrides.clone()))
+    }
+}
+
+/// Default config file location: `<config dir>/talon/config.toml`.
+///
+/// `$XDG_CONFIG_HOME` (or `~/.config`) on Linux, `~/Library/Application Support`
+/// on macOS.
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs_next::config_dir().map(|d| d.join("talon").join("config.toml"))
+}
+
+/// Where the effective settings came from, for `config show`.
+pub struct Loaded {
+    pub settings: Settings,
+    pub path: Option<PathBuf>,
+    pub profile: Option<String>,
+}
+
+/// Loads the config file and layers defaults, file, and CLI/env settings.
+///
+/// An explicit `path` must exist; the default path is optional. A `profile` must
+/// exist in the file.
+///
+/// # Errors
+///
+/// Returns an error if the file can't be read or parsed, or the profile is unknown.
+pub fn load(path: Option<&Path>, profile: Option<&str>, cli: Settings) -> Result<Loaded> {
+    let path = match path {
+        Some(p) => Some(p.to_path_buf()),
+        None => default_config_path().filter(|p| p.exists()),
+    };
+
+    let (file_settings, profile) = match &path {
+        Some(p) => {
+            let text = fs::read_to_string(p)
+                .with_context(|| format!("read config file {}", p.display()))?;
+            let file = ConfigFile::parse(&text)
+                .with_context(|| format!("parse config file {}", p.display()))?;
+            let profile = profile
+                .map(str::to_string)
+                .or_else(|| file.default_profile.clone());
+            (file.settings(profile.as_deref())?, profile)
+        }
+        None if profile.is_some() => {
+            return Err(anyhow!(
+                "profile `{}` requested but no config file found",
+                profile.unwrap_or_default()
+            ));
+        }
+        None => (Settings::default(), None),
+    };
+
+    Ok(Loaded {
+        settings: Settings::defaults()?.overlay(file_settings).overlay(cli),
+        path,
+        profile,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    const SAMPLE: &str = r#"
+endpoint = "https://collector.example.com/v1/traces"
+batch_size = 50
+profile = "dev"
+
+[profiles.dev]
+endpoint = "http://localhost:3000/v1/traces"
+batch_ms = 25
+
+[profiles.prod]
+batch_size = 500
+"#;
+
+    #[test]
+    fn test_parse_base_and_profiles() {
+        let file = ConfigFile::parse(SAMPLE).unwrap();
+        assert_eq!(file.default_profile.as_deref(), Some("dev"));
+        assert_eq!(file.base.batch_size, Some(50));
+        assert_eq!(file.profiles.len(), 2);
+        assert_eq!(file.profiles["prod"].batch_size, Some(500));
+    }
+
+    #[test]
+    fn test_profile_overrides_base() {
+        let file = ConfigFile::parse(SAMPLE).unwrap();
+
+        let dev = file.settings(None).unwrap();
+        assert_eq!(
+            dev.endpoint.as_deref(),
+            Some("http://localhost:3000/v1/traces")
+        );
+        assert_eq!(dev.batch_ms, Some(25));
+        assert_eq!(dev.batch_size, Some(50), "base value kept");
+
+        let prod = file.settings(Some("prod")).unwrap();
+        assert_eq!(
+            prod.endpoint.as_deref(),
+            Some("https://collector.example.com/v1/traces")
+        );
+        assert_eq!(prod.batch_size, Some(500));
+
+        assert!(file.settings(Some("staging")).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys() {
+        assert!(ConfigFile::parse("batch_sise = 10").is_err());
+        assert!(ConfigFile::parse("[profiles.dev]\nendpont = \"x\"").is_err());
+        assert!(ConfigFile::parse("batch_size = \"ten\"").is_err());
+    }
+
+    #[test]
+    fn test_load_precedence_cli_over_file_over_defaults() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(SAMPLE.as_bytes()).unwrap();
+
+        let cli = Settings {
+            batch_ms: Some(999),
+            ..Default::default()
+        };
+        let loaded = load(Some(file.path()), Some("prod"), cli).unwrap();
+        let s = loaded.settings;
+
+        assert_eq!(loaded.profile.as_deref(), Some("prod"));
+        assert_eq!(s.batch_ms, Some(999), "CLI wins");
+        assert_eq!(s.batch_size, Some(500), "profile beats base");
+        assert_eq!(s.chan_capacity, Some(10_000), "default fills the rest");
+        assert_eq!(s.sock.as_deref(), Some("/tmp/talon.sock"));
+    }
+
+    #[test]
+    fn test_load_missing_explicit_file_is_error() {
+        let err = load(
+            Some(Path::new("/nonexistent/talon.toml")),
+            None,
+            Settings::default(),
+        );
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_into_config_requires_endpoint() {
+        let s = Settings::defaults().unwrap();
+        assert!(s.clone().into_config().is_err());
+
+        let config = s
+            .overlay(Settings {
+                endpoint: Some("http://localhost".into()),
+                ..Default::default()
+            })
+            .into_config()
+            .unwrap();
+        assert_eq!(config.endpoint, "http://localhost");
+        assert_eq!(config.batch_size, 100);
+    }
+
+    #[test]
+    fn test_api_key_sources_overlay_as_one_setting() {
+        let file = Settings {
+            endpoint: Some("http://localhost".into()),
+            api_key_cmd: Some("pass show talon".into()),
+            ..Default::default()
+        };
+        let env = Settings {
+            api_key: Some("from-env".into()),
+            ..Default::default()
+        };
+
+        let merged = file.clone().overlay(env.clone());
+        assert_eq!(merged.api_key.as_deref(), Some("from-env"));
+        assert_eq!(merged.api_key_cmd, None, "lower layer's source is replaced");
+        let config = merged.into_config().unwrap();
+        let key = config.api_key.unwrap();
+        assert_eq!(key.source(), &KeySource::Value("from-env".into()));
+
+        let merged = file.clone().overlay(Settings::default());
+        assert_eq!(merged.api_key_cmd.as_deref(), Some("pass show talon"));
+
+        let conflicting = Settings {
+            api_key_file: Some("/run/secrets/talon".into()),
+            ..env.overlay(file)
+        };
+        assert!(conflicting.into_config().is_err());
+    }
+
+    #[test]
+    fn test_pricing_merges_per_model_and_rejects_negative_prices() {
+        let file = ConfigFile::parse(
+            r#"
+            endpoint = "http://base"
+
+            [pricing.a]
+            input = 1.0
+            output = 2.0
+
+            [profiles.p.pricing.b]
+            input = 3.0
+            output = 4.0
+            "#,
+        )
+        .unwrap();
+        let pricing = file.settings(Some("p")).unwrap().pricing.unwrap();
+        assert_eq!(pricing.keys().collect::<Vec<_>>(), ["a", "b"]);
+
+        let bad =
+            ConfigFile::parse("endpoint = \"http://x\"\n[pricing.a]\ninput = -1.0\noutput = 1.0\n")
+                .unwrap();
+        let err = bad.base.into_config().err().unwrap();
+        assert!(err.to_string().contains("pricing for `a`"), "{err}");
+    }
+
+    #[test]
+    fn test_exporters_and_processors_merge_per_key() {
+        let file = ConfigFile::parse(
+            r#"
+            endpoint = "http://base"
+
+            [exporters.beak]
+            collection = "ci"
+
+            [processors]
+            drop_events = ["tool.pre"]
+
+            [profiles.p.exporters.beak]
+            flow = "nightly"
+
+            [profiles.p.processors]
+            git = false
+            "#,
+        )
+        .unwrap();
+        let config = Settings::defaults()
+            .unwrap()
+            .overlay(file.settings(Some("p")).unwrap())
+            .into_config()
+            .unwrap();
+        assert_eq!(config.beak.collection, "ci");
+        assert_eq!(config.beak.flow, "nightly");
+        assert!(!config.map.git);
+        assert!(config.processors.drop_events.contains("tool.pre"));
+        assert!(config.processors.drop_labels.is_empty());
+
+        assert!(ConfigFile::parse("[exporters.otlp]\nendpoint = \"x\"").is_err());
+        assert!(ConfigFile::parse("[processors]\nredact = true").is_err());
+        let empty =
+            ConfigFile::parse("endpoint = \"http://x\"\n[exporters.beak]\nflow = \"\"").unwrap();
+        assert!(empty.base.into_config().is_err());
+    }
+
+    #[test]
+    fn test_redacted_hides_api_key() {
+        let s = Settings {
+            api_key: Some("secret".into()),
+            ..Default::default()
+        };
+        let shown = toml::to_string(&s.redacted()).unwrap();
+        assert!(!shown.contains("secret"));
+        assert!(shown.contains("<redacted>"));
+    }
+}
@@ -0,0 +1,98 @@
+//! Context-window sizes and utilization.
+//!
+//! The context an assistant message was generated from is its prompt: uncached
+//! input plus cache writes and reads, i.e. `metrics.prompt_tokens` before usage
+//! attribution. Events carrying a message get it as labels:
+//!
+//! - `context_tokens`: the prompt size,
+//! - `context_window`: the model's window, from [`WINDOWS`],
+//! - `context_used_pct`: `context_tokens` as a percentage of the window.
+//!
+//! Models are matched like pricing entries: the ID equals the key or continues
+//! it after a `-` or `@`, and the longest key wins.
+
+use crate::schema::TraceV1;
+
+/// Label holding the message's prompt size in tokens.
+pub const CONTEXT_TOKENS: &str = "context_tokens";
+
+/// Standard and extended (beta) context windows in tokens.
+const WINDOWS: &[(&str, u32, Option<u32>)] = &[
+    ("claude-opus-4-5", 200_000, None),
+    ("claude-opus-4-1", 200_000, None),
+    ("claude-opus-4", 200_000, None),
+    ("claude-sonnet-4-5", 200_000, Some(1_000_000)),
+    ("claude-sonnet-4", 200_000, Some(1_000_000)),
+    ("claude-haiku-4-5", 200_000, None),
+    ("claude-3-7-sonnet", 200_000, None),
+    ("claude-3-5-sonnet", 200_000, None),
+    ("claude-3-5-haiku", 200_000, None),
+    ("claude-3-opus", 200_000, None),
+    ("claude-3-haiku", 200_000, None),
+];
+
+/// Context window of `model` for a prompt of `tokens`.
+///
+/// The extended window isn't visible in the model ID, so it is assumed once a
+/// prompt no longer fits the standard one.
+pub fn window(model: &str, tokens: u32) -> Option<u32> {
+    let (_, standard, extended) = WINDOWS
+        .iter()
+        .filter(|(key, _, _)| crate::pricing::model_matches(model, key))
+        .max_by_key(|(key, _, _)| key.len())?;
+    match extended {
+        Some(extended) if tokens > *standard => Some(*extended),
+        _ => Some(*standard),
+    }
+}
+
+/// Labels `t` with its message's context size and window utilization.
+pub fn apply(t: &mut TraceV1) {
+    let tokens = t.metrics.prompt_tokens;
+    if t.ids.message_id.is_empty() || tokens == 0 {
+        return;
+    }
+    t.set_label(CONTEXT_TOKENS, tokens.to_string());
+    if let Some(window) = window(&t.configuration.model, tokens) {
+        let pct = f64::from(tokens) * 100.0 / f64::from(window);
+        t.set_label("context_window", window.to_string());
+        t.set_label("context_used_pct", format!("{pct:.1}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_lookup() {
+        assert_eq!(window("claude-opus-4-5-20251101", 50_000), Some(200_000));
+        assert_eq!(window("claude-sonnet-4-5-20250929", 150_000), Some(200_000));
+        assert_eq!(
+            window("claude-sonnet-4-5-20250929", 350_000),
+            Some(1_000_000),
+            "only a 1M window fits"
+        );
+        assert_eq!(window("claude-haiku-4-5", 250_000), Some(200_000));
+        assert_eq!(window("gpt-4o", 1), None);
+    }
+
+    #[test]
+    fn test_apply_labels_utilization() {
+        let mut t = TraceV1::default();
+        t.ids.message_id = "msg_1".to_string();
+        t.configuration.model = "claude-sonnet-4-5-20250929".to_string();
+        t.metrics.prompt_tokens = 50_000;
+        apply(&mut t);
+        assert_eq!(t.label(CONTEXT_TOKENS), Some("50000"));
+        assert_eq!(t.label("context_window"), Some("200000"));
+        assert_eq!(t.label("context_used_pct"), Some("25.0"));
+
+        let mut unknown = TraceV1::default();
+        unknown.ids.message_id = "msg_2".to_string();
+        unknown.metrics.prompt_tokens = 10;
+        apply(&mut unknown);
+        assert_eq!(unknown.label(CONTEXT_TOKENS), Some("10"));
+        assert_eq!(unknown.label("context_window"), None);
+    }
+}
@@ -0,0 +1,558 @@
+//! Control channel for a running agent.
+//!
+//! The agent listens on a second socket next to the event socket (`<sock>.ctl` on
+//! Unix, `127.0.0.1:7879` on Windows). Clients send one line-delimited JSON request
+//! (e.g. `{"cmd":"status"}`) and receive one JSON response line.
+//!
+//! Read-only commands (`status`, `stats`) and `pause` are answered directly from
+//! the shared [`AgentState`]. Commands that touch the in-memory batch (`flush`,
+//! `resume`, `drain`) are forwarded to the batching loop as [`LoopCmd`]s, which
+//! owns the batch buffer and replies once the work is done. `reload` is delegated
+//! to the agent through [`Control::reload`]. `tail` acknowledges and then keeps
+//! the connection open, streaming one [`TailFrame`] per line.
+
+use crate::Config;
+use crate::tail::{TailFilter, TailFrame, TailHub};
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use crossbeam_channel as chan;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Windows control address. Must not collide with the event port (7878).
+#[cfg(not(unix))]
+pub const CONTROL_ADDR: &str = "127.0.0.1:7879";
+
+/// How long a control client waits for the agent to answer.
+///
+/// Drain may need to push a full batch through `send_batch` retries (~3s worst case)
+/// before replying, so this is deliberately generous.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Derives the control socket path from the event socket path.
+pub fn control_path(sock: &str) -> String {
+    format!("{sock}.ctl")
+}
+
+/// Request sent by control clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum Request {
+    /// Config, uptime, counters, spool backlog and destination health.
+    Status,
+    /// Counters only.
+    Stats,
+    /// Send the in-memory batch now and try to drain the spool.
+    Flush,
+    /// Stop sending; due batches are spooled to disk instead.
+    Pause,
+    /// Resume sending and drain anything spooled while paused.
+    Resume,
+    /// Stop accepting events, flush everything, then exit.
+    Drain,
+    /// Re-read the config file and apply it without dropping queued events.
+    Reload,
+    /// Stream processed frames matching the filter until the client disconnects.
+    Tail(TailFilter),
+}
+
+/// Work item handed from the control thread to the batching loop.
+///
+/// Each variant carries a reply channel so the control client can block until the
+/// loop has acted on it.
+pub enum LoopCmd {
+    Flush(chan::Sender<Json>),
+    Drain(chan::Sender<Json>),
+    /// Replace the loop's configuration; already validated by the sender.
+    Reload(Box<Config>, chan::Sender<Json>),
+}
+
+/// Monotonic event counters.
+#[derive(Default)]
+pub struct Counters {
+    /// Frames read from the event socket.
+    pub received: AtomicU64,
+    /// Frames successfully mapped to TraceV1.
+    pub mapped: AtomicU64,
+    /// Frames written to the quarantine file.
+    pub quarantined: AtomicU64,
+    /// Events accepted by the collector.
+    pub sent: AtomicU64,
+    /// Events written to the spool after a failed or paused send.
+    pub spooled: AtomicU64,
+    /// Batches accepted by the collector.
+    pub batches_sent: AtomicU64,
+    /// Batches that exhausted retries or were rejected.
+    pub batches_failed: AtomicU64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> Json {
+        serde_json::json!({
+            "received": self.received.load(Ordering::Relaxed),
+            "mapped": self.mapped.load(Ordering::Relaxed),
+            "quarantined": self.quarantined.load(Ordering::Relaxed),
+            "sent": self.sent.load(Ordering::Relaxed),
+            "spooled": self.spooled.load(Ordering::Relaxed),
+            "batches_sent": self.batches_sent.load(Ordering::Relaxed),
+            "batches_failed": self.batches_failed.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Outcome of recent sends to the collector.
+#[derive(Default)]
+struct Health {
+    last_success: Option<DateTime<Utc>>,
+    last_error: Option<(DateTime<Utc>, String)>,
+    consecutive_failures: u32,
+}
+
+/// Parts of the active configuration the control thread needs.
+struct ConfigView {
+    /// Redacted view of the runtime configuration reported by `status`.
+    summary: Json,
+    spool_dir: PathBuf,
+}
+
+/// State shared between the listener, the batching loop and the control thread.
+pub struct AgentState {
+    started: Instant,
+    started_at: DateTime<Utc>,
+    config: Mutex<ConfigView>,
+    paused: AtomicBool,
+    pub counters: Counters,
+    health: Mutex<Health>,
+    /// Outcome of the most recent reload, if any.
+    last_reload: Mutex<Option<Json>>,
+    /// Live `tail` subscribers.
+    pub tail: TailHub,
+}
+
+impl AgentState {
+    pub fn new(summary: Json, spool_dir: PathBuf) -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: Utc::now(),
+            config: Mutex::new(ConfigView { summary, spool_dir }),
+            paused: AtomicBool::new(false),
+            counters: Counters::default(),
+            health: Mutex::new(Health::default()),
+            last_reload: Mutex::new(None),
+            tail: TailHub::default(),
+        }
+    }
+
+    /// Publishes a newly applied configuration to `status`.
+    pub fn set_config(&self, summary: Json, spool_dir: PathBuf) {
+        *self.config.lock().unwrap_or_else(|e| e.into_inner()) = ConfigView { summary, spool_dir };
+    }
+
+    /// Records the outcome of a reload attempt for `status`.
+    pub fn record_reload(&self, result: Result<(), String>) {
+        let entry = match result {
+            Ok(()) => serde_json::json!({ "at": Utc::now().to_rfc3339(), "ok": true }),
+            Err(e) => serde_json::json!({ "at": Utc::now().to_rfc3339(), "ok": false, "error": e }),
+        };
+        *self.last_reload.lock().unwrap_or_else(|e| e.into_inner()) = Some(entry);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Records a batch the collector accepted.
+    pub fn record_success(&self, events: usize) {
+        self.counters
+            .sent
+            .fetch_add(events as u64, Ordering::Relaxed);
+        self.counters.batches_sent.fetch_add(1, Ordering::Relaxed);
+        let mut health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        health.last_success = Some(Utc::now());
+        health.consecutive_failures = 0;
+    }
+
+    /// Records a batch that could not be delivered.
+    pub fn record_failure(&self, err: &anyhow::Error) {
+        self.counters.batches_failed.fetch_add(1, Ordering::Relaxed);
+        let mut health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        health.last_error = Some((Utc::now(), err.to_string()));
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+    }
+
+    fn health_snapshot(&self) -> Json {
+        let health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        serde_json::json!({
+            "healthy": health.consecutive_failures == 0,
+            "consecutive_failures": health.consecutive_failures,
+            "last_success": health.last_success.map(|t| t.to_rfc3339()),
+            "last_error": health.last_error.as_ref().map(|(t, e)| {
+                serde_json::json!({ "at": t.to_rfc3339(), "error": e })
+            }),
+        })
+    }
+
+    /// Builds the `status` response.
+    pub fn status(&self) -> Json {
+        let (summary, spool_dir) = {
+            let view = self.config.lock().unwrap_or_else(|e| e.into_inner());
+            (view.summary.clone(), view.spool_dir.clone())
+        };
+        let (spool_events, spool_bytes) = spool_backlog(&spool_dir);
+        let last_reload = self
+            .last_reload
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        serde_json::json!({
+            "ok": true,
+            "pid": std::process::id(),
+            "version": env!("CARGO_PKG_VERSION"),
+            "started_at": self.started_at.to_rfc3339(),
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "paused": self.is_paused(),
+            "config": summary,
+            "counters": self.counters.snapshot(),
+            "spool": { "events": spool_events, "bytes": spool_bytes },
+            "destination": self.health_snapshot(),
+            "last_reload": last_reload,
+        })
+    }
+
+    /// Builds the `stats` response.
+    pub fn stats(&self) -> Json {
+        serde_json::json!({
+            "ok": true,
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "counters": self.counters.snapshot(),
+        })
+    }
+}
+
+/// Counts spooled events and bytes without taking the spool lock.
+///
+/// The result is advisory: a concurrent append or rotation can change it
+/// immediately after it is read.
+pub fn spool_backlog(dir: &Path) -> (u64, u64) {
+    let file_path = dir.join("events.jsonl");
+    let Ok(file) = File::open(&file_path) else {
+        return (0, 0);
+    };
+    let bytes = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let events = BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|l| !l.trim().is_empty())
+        .count() as u64;
+    (events, bytes)
+}
+
+pub fn error_response(msg: impl std::fmt::Display) -> Json {
+    serde_json::json!({ "ok": false, "error": msg.to_string() })
+}
+
+/// Hands a command to the batching loop and waits for its reply.
+pub fn forward(
+    loop_tx: &chan::Sender<LoopCmd>,
+    make: impl FnOnce(chan::Sender<Json>) -> LoopCmd,
+) -> Json {
+    let (reply_tx, reply_rx) = chan::bounded(1);
+    if loop_tx.send(make(reply_tx)).is_err() {
+        return error_response("batching loop is not running");
+    }
+    reply_rx
+        .recv()
+        .unwrap_or_else(|_| error_response("batching loop exited before replying"))
+}
+
+/// Everything a control connection needs from the running agent.
+pub struct Control {
+    pub state: Arc<AgentState>,
+    pub loop_tx: chan::Sender<LoopCmd>,
+    /// Runs before a drain is forwarded so the agent stops accepting new events.
+    pub on_drain: Box<dyn Fn() + Send + Sync>,
+    /// Re-reads, validates and applies configuration; returns the response.
+    pub reload: Box<dyn Fn() -> Json + Send + Sync>,
+}
+
+/// Dispatches one request and returns the response plus whether the agent should exit.
+pub fn dispatch(req: Request, ctl: &Control) -> (Json, bool) {
+    let state = &ctl.state;
+    match req {
+        Request::Status => (state.status(), false),
+        Request::Stats => (state.stats(), false),
+        Request::Pause => {
+            state.set_paused(true);
+            (serde_json::json!({ "ok": true, "paused": true }), false)
+        }
+        Request::Resume => {
+            state.set_paused(false);
+            (forward(&ctl.loop_tx, LoopCmd::Flush), false)
+        }
+        Request::Flush => (forward(&ctl.loop_tx, LoopCmd::Flush), false),
+        Request::Drain => {
+            (ctl.on_drain)();
+            (forward(&ctl.loop_tx, LoopCmd::Drain), true)
+        }
+        Request::Reload => ((ctl.reload)(), false),
+        Request::Tail(_) => (error_response("tail must be served by handle_conn"), false),
+    }
+}
+
+/// Serves a single control connection.
+///
+/// Returns `true` when the client asked the agent to drain and exit; the caller
+/// terminates the process.
+pub fn handle_conn<S>(stream: S, ctl: &Control) -> bool
+where
+    for<'a> &'a S: Read + Write,
+{
+    let mut exit = false;
+    let reader = BufReader::new(&stream);
+    for line in reader.lines().map_while(Result::ok) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let resp = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Tail(filter)) => {
+                stream_tail(&stream, ctl.state.tail.subscribe(filter));
+                break;
+            }
+            Ok(req) => {
+                let (resp, should_exit) = dispatch(req, ctl);
+                exit |= should_exit;
+                resp
+            }
+            Err(e) => error_response(format!("bad request: {e}")),
+        };
+        let mut w = &stream;
+        if writeln!(w, "{resp}").and_then(|_| w.flush()).is_err() || exit {
+            break;
+        }
+    }
+    exit
+}
+
+/// Acknowledges a tail subscription and forwards frames until the client goes away.
+fn stream_tail<S>(stream: &S, frames: chan::Receiver<TailFrame>)
+where
+    for<'a> &'a S: Write,
+{
+    let mut w = stream;
+    let ack = serde_json::json!({ "ok": true, "subscribed": true });
+    if writeln!(w, "{ack}").and_then(|_| w.flush()).is_err() {
+        return;
+    }
+    for frame in frames {
+        let Ok(line) = serde_json::to_string(&frame) else {
+            continue;
+        };
+        if writeln!(w, "{line}").and_then(|_| w.flush()).is_err() {
+            // Client disconnected; dropping `frames` unregisters us on next publish.
+            return;
+        }
+    }
+}
+
+#[cfg(unix)]
+type ClientStream = std::os::unix::net::UnixStream;
+#[cfg(not(unix))]
+type ClientStream = std::net::TcpStream;
+
+/// Connects to a running agent's control socket and sends one request.
+fn send_request(sock: &str, req: &Request) -> Result<BufReader<ClientStream>> {
+    #[cfg(unix)]
+    let stream = {
+        let path = control_path(sock);
+        ClientStream::connect(&path)
+            .with_context(|| format!("connect control socket {path} (is talon-agent running?)"))?
+    };
+    #[cfg(not(unix))]
+    let stream = {
+        let _ = sock;
+        ClientStream::connect(CONTROL_ADDR)
+            .with_context(|| format!("connect control {CONTROL_ADDR} (is talon-agent running?)"))?
+    };
+
+    let mut w = &stream;
+    writeln!(w, "{}", serde_json::to_string(req)?)?;
+    w.flush()?;
+    Ok(BufReader::new(stream))
+}
+
+/// Reads one JSON response line.
+fn read_response(reader: &mut impl BufRead) -> Result<Json> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim().is_empty() {
+        return Err(anyhow!("agent closed control connection without replying"));
+    }
+    serde_json::from_str(line.trim()).context("parse control response")
+}
+
+/// Sends one request to a running agent and returns its response.
+///
+/// # Errors
+///
+/// Returns an error if no agent is listening, the connection drops before a reply
+/// arrives, or the reply is not valid JSON.
+pub fn request(sock: &str, req: Request) -> Result<Json> {
+    let mut reader = send_request(sock, &req)?;
+    reader.get_ref().set_read_timeout(Some(CLIENT_TIMEOUT)).ok();
+    read_response(&mut reader)
+}
+
+/// Subscribes to the agent's live frame stream.
+///
+/// Blocks indefinitely between frames; the iterator ends when the agent exits.
+///
+/// # Errors
+///
+/// Returns an error if no agent is listening or the subscription is refused.
+pub fn subscribe(sock: &str, filter: TailFilter) -> Result<impl Iterator<Item = TailFrame>> {
+    let mut reader = send_request(sock, &Request::Tail(filter))?;
+    let ack = read_response(&mut reader)?;
+    if ack.get("ok").and_then(|v| v.as_bool()) != Some(true) {
+        return Err(anyhow!("agent refused tail subscription: {ack}"));
+    }
+    Ok(reader
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<TailFrame>(&line).ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn state() -> AgentState {
+        AgentState::new(
+            serde_json::json!({ "endpoint": "http://localhost" }),
+            PathBuf::from("/nonexistent/spool"),
+        )
+    }
+
+    fn control(loop_tx: chan::Sender<LoopCmd>) -> Control {
+        Control {
+            state: Arc::new(state()),
+            loop_tx,
+            on_drain: Box::new(|| {}),
+            reload: Box::new(|| serde_json::json!({ "ok": true, "reloaded": true })),
+        }
+    }
+
+    #[test]
+    fn test_request_wire_format() {
+        assert_eq!(
+            serde_json::to_string(&Request::Status).unwrap(),
+            r#"{"cmd":"status"}"#
+        );
+        let req: Request = serde_json::from_str(r#"{"cmd":"drain"}"#).unwrap();
+        assert_eq!(req, Request::Drain);
+        let req: Request = serde_json::from_str(r#"{"cmd":"tail","tool":"Bash"}"#).unwrap();
+        assert_eq!(
+            req,
+            Request::Tail(TailFilter {
+                tool: Some("Bash".into()),
+                ..Default::default()
+            })
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"cmd":"reboot"}"#).is_err());
+    }
+
+    #[test]
+    fn test_spool_backlog_counts_lines_and_bytes() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(spool_backlog(temp_dir.path()), (0, 0));
+
+        std::fs::write(temp_dir.path().join("events.jsonl"), "{}\n{}\n{}\n").unwrap();
+        assert_eq!(spool_backlog(temp_dir.path()), (3, 9));
+    }
+
+    #[test]
+    fn test_status_reports_health_and_counters() {
+        let temp_dir = TempDir::new().unwrap();
+        let state = state();
+        state.set_config(
+            serde_json::json!({ "endpoint": "http://localhost" }),
+            temp_dir.path().to_path_buf(),
+        );
+        state.counters.received.fetch_add(5, Ordering::Relaxed);
+        state.record_success(5);
+        state.record_failure(&anyhow!("collector returned 4xx: 400"));
+        state.record_reload(Err("unknown profile `x`".to_string()));
+
+        let status = state.status();
+        assert_eq!(status["counters"]["received"], 5);
+        assert_eq!(status["counters"]["sent"], 5);
+        assert_eq!(status["counters"]["batches_failed"], 1);
+        assert_eq!(status["destination"]["healthy"], false);
+        assert_eq!(status["destination"]["consecutive_failures"], 1);
+        assert_eq!(status["config"]["endpoint"], "http://localhost");
+        assert_eq!(status["paused"], false);
+        assert_eq!(status["spool"]["events"], 0);
+        assert_eq!(status["last_reload"]["ok"], false);
+    }
+
+    #[test]
+    fn test_dispatch_pause_and_forwarded_commands() {
+        let (loop_tx, loop_rx) = chan::unbounded::<LoopCmd>();
+        let ctl = control(loop_tx);
+
+        // Stand-in for the batching loop: acknowledge every forwarded command.
+        let worker = std::thread::spawn(move || {
+            for cmd in loop_rx {
+                match cmd {
+                    LoopCmd::Flush(reply) | LoopCmd::Drain(reply) | LoopCmd::Reload(_, reply) => {
+                        reply.send(serde_json::json!({"ok": true})).unwrap()
+                    }
+                }
+            }
+        });
+
+        let (resp, exit) = dispatch(Request::Pause, &ctl);
+        assert_eq!(resp["paused"], true);
+        assert!(!exit);
+        assert!(ctl.state.is_paused());
+
+        let (resp, exit) = dispatch(Request::Resume, &ctl);
+        assert_eq!(resp["ok"], true);
+        assert!(!exit);
+        assert!(!ctl.state.is_paused());
+
+        let (resp, exit) = dispatch(Request::Reload, &ctl);
+        assert_eq!(resp["reloaded"], true);
+        assert!(!exit);
+
+        let (_, exit) = dispatch(Request::Drain, &ctl);
+        assert!(exit, "drain should ask the caller to exit");
+
+        drop(ctl);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn test_dispatch_reports_dead_loop() {
+        let (loop_tx, loop_rx) = chan::unbounded::<LoopCmd>();
+        drop(loop_rx);
+        let ctl = control(loop_tx);
+
+        let (resp, _) = dispatch(Request::Flush, &ctl);
+        assert_eq!(resp["ok"], false);
+    }
+}
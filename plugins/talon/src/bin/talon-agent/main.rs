@@ -4,15 +4,35 @@
 //! to a trace collector with retry logic and disk spooling.
 
 mod beak_adapter;
+mod config;
+mod context;
+mod control;
+mod git;
 mod map;
+mod outcome;
+mod pricing;
+mod processors;
+mod rollup;
 mod schema;
-
-use crate::beak_adapter::to_beak_format;
+mod secret;
+mod spans;
+mod tail;
+mod transcript;
+mod usage;
+
+use crate::beak_adapter::{BeakOptions, to_beak_format};
+use crate::config::Settings;
+use crate::control::{AgentState, LoopCmd, Request};
 use crate::map::from_tap_frame;
+use crate::rollup::SessionRollup;
 use crate::schema::canonicalize;
+use crate::secret::ApiKey;
+use crate::spans::SpanTracker;
+use crate::tail::{TailFilter, render_pretty};
+use crate::usage::UsageLedger;
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use crossbeam_channel as chan;
 use flate2::{Compression, write::GzEncoder};
 use fs2::FileExt;
@@ -21,22 +41,82 @@ use std::{
     fs::{self, File, OpenOptions},
     io::{BufRead, BufReader, Write},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, atomic::Ordering},
     thread,
     time::{Duration, Instant},
 };
 
 /// Configuration for the agent runtime
 struct Config {
+    sock: String,
     endpoint: String,
-    api_key: Option<String>,
+    api_key: Option<ApiKey>,
     batch_size: usize,
     batch_ms: u64,
     chan_capacity: usize,
     batch_bytes: usize,
     spool_dir: PathBuf,
     spool_bytes: u64,
+    span_timeout_secs: u64,
+    map: crate::map::MapOptions,
+    pricing: crate::pricing::PriceTable,
+    beak: BeakOptions,
+    processors: crate::processors::Processors,
+}
+
+impl Config {
+    /// Configuration as reported by `talon-agent status`. Never includes secrets.
+    fn summary(&self) -> Json {
+        serde_json::json!({
+            "sock": self.sock,
+            "endpoint": self.endpoint,
+            "api_key_set": self.api_key.is_some(),
+            "api_key_source": self.api_key.as_ref().map(|k| k.source().kind()),
+            "batch_size": self.batch_size,
+            "batch_ms": self.batch_ms,
+            "chan_capacity": self.chan_capacity,
+            "batch_bytes": self.batch_bytes,
+            "spool_dir": self.spool_dir,
+            "spool_bytes": self.spool_bytes,
+            "span_timeout_secs": self.span_timeout_secs,
+            "capture_messages": self.map.capture,
+            "capture_max_chars": self.map.max_chars,
+            "exporters": { "beak": self.beak },
+            "processors": {
+                "git": self.map.git,
+                "drop_events": self.processors.drop_events,
+                "drop_labels": self.processors.drop_labels,
+            },
+        })
+    }
+
+    /// Resolves the API key for the next request.
+    fn api_key(&self) -> Result<Option<String>> {
+        self.api_key.as_ref().map(ApiKey::get).transpose()
+    }
+
+    /// Lets a rotated key be picked up after the collector rejects the current one.
+    fn on_send_error(&self, e: &anyhow::Error) {
+        if let Some(key) = &self.api_key
+            && e.downcast_ref::<AuthRejected>().is_some()
+        {
+            key.invalidate();
+        }
+    }
 }
 
+/// The collector rejected the request's credentials (401 or 403).
+#[derive(Debug)]
+struct AuthRejected(reqwest::StatusCode);
+
+impl std::fmt::Display for AuthRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "collector returned 4xx: {}", self.0)
+    }
+}
+
+impl std::error::Error for AuthRejected {}
+
 /// RAII guard for spool directory lock.
 ///
 /// Automatically releases the lock on drop, preventing lock leaks
@@ -54,6 +134,7 @@ impl SpoolLockGuard {
         let lock_path = dir.join(".spool.lock");
         let file = OpenOptions::new()
             .create(true)
+            .truncate(false)
             .write(true)
             .open(&lock_path)
             .context("failed to open spool lock file")?;
@@ -80,129 +161,676 @@ struct Cli {
     cmd: Cmd,
 }
 
+/// API key source. The key itself belongs in the environment, a file or a helper
+/// command, never on the command line where other users can read it from `ps`.
+#[derive(Args)]
+struct KeyArgs {
+    /// Deprecated: exposes the key in `ps`. Still honored, with a warning
+    #[arg(long, hide = true)]
+    api_key: Option<String>,
+
+    /// Read the collector API key from this file (otherwise TRACE_API_KEY)
+    #[arg(long, env = "TALON_API_KEY_FILE")]
+    api_key_file: Option<PathBuf>,
+
+    /// Run this command and use its output as the API key
+    #[arg(long, env = "TALON_API_KEY_CMD")]
+    api_key_cmd: Option<String>,
+
+    /// Seconds before re-reading the key file or re-running the key command [default: 300]
+    #[arg(long, env = "TALON_API_KEY_REFRESH_SECS")]
+    api_key_refresh_secs: Option<u64>,
+}
+
+impl KeyArgs {
+    /// The flag/env key settings. `TRACE_API_KEY` is read from the environment
+    /// only, and only when no file or command was given at this layer.
+    fn into_settings(self) -> Settings {
+        if self.api_key.is_some() {
+            eprintln!(
+                "talon-agent: warning: --api-key exposes the key to other users via the \
+                 process list; use TRACE_API_KEY, --api-key-file or --api-key-cmd"
+            );
+        }
+        let api_key = if self.api_key_file.is_none() && self.api_key_cmd.is_none() {
+            self.api_key
+                .or_else(|| std::env::var("TRACE_API_KEY").ok())
+                .filter(|k| !k.is_empty())
+        } else {
+            self.api_key
+        };
+        Settings {
+            api_key,
+            api_key_file: self.api_key_file,
+            api_key_cmd: self.api_key_cmd,
+            api_key_refresh_secs: self.api_key_refresh_secs,
+            ..Default::default()
+        }
+    }
+}
+
+/// Config file selection, shared by every subcommand.
+#[derive(Args)]
+struct ConfigArgs {
+    /// Config file [default: <config dir>/talon/config.toml]
+    #[arg(long, env = "TALON_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Named profile from the config file
+    #[arg(long, env = "TALON_PROFILE")]
+    profile: Option<String>,
+}
+
+/// Agent settings. Unset flags fall back to the environment, then the config
+/// file, then built-in defaults (see [`config`]).
+#[derive(Args)]
+struct AgentArgs {
+    #[command(flatten)]
+    files: ConfigArgs,
+
+    /// Event socket path [default: /tmp/talon.sock]
+    #[arg(long, env = "TALON_SOCK")]
+    sock: Option<String>,
+
+    /// Trace collector HTTP endpoint
+    #[arg(long, env = "TRACE_ENDPOINT")]
+    endpoint: Option<String>,
+
+    #[command(flatten)]
+    key: KeyArgs,
+
+    /// Max events per batch [default: 100]
+    #[arg(long, env = "TALON_BATCH_SIZE")]
+    batch_size: Option<usize>,
+
+    /// Max milliseconds before flush [default: 200]
+    #[arg(long, env = "TALON_BATCH_MS")]
+    batch_ms: Option<u64>,
+
+    /// Internal channel buffer size [default: 10000]
+    #[arg(long, env = "TALON_CHAN_CAPACITY")]
+    chan_capacity: Option<usize>,
+
+    /// Max batch size in bytes [default: 1048576]
+    #[arg(long, env = "TALON_BATCH_BYTES")]
+    batch_bytes: Option<usize>,
+
+    /// Max spool file size in bytes [default: 50000000]
+    #[arg(long, env = "TALON_SPOOL_BYTES")]
+    spool_bytes: Option<u64>,
+
+    /// Spool directory [default: platform-specific]
+    #[arg(long, env = "TALON_SPOOL_DIR")]
+    spool_dir: Option<PathBuf>,
+
+    /// Seconds a tool start waits for its end before being sent as incomplete [default: 900]
+    #[arg(long, env = "TALON_SPAN_TIMEOUT_SECS")]
+    span_timeout_secs: Option<u64>,
+
+    /// Copy each turn's messages into agent.stop events [default: off]
+    #[arg(long, value_enum, env = "TALON_CAPTURE_MESSAGES")]
+    capture_messages: Option<crate::map::CaptureMode>,
+
+    /// Characters kept per captured message [default: 2000]
+    #[arg(long, env = "TALON_CAPTURE_MAX_CHARS")]
+    capture_max_chars: Option<usize>,
+}
+
+impl AgentArgs {
+    /// Layers these flags over the config file and defaults.
+    fn load(self) -> Result<config::Loaded> {
+        let (files, cli) = self.into_parts();
+        config::load(files.config.as_deref(), files.profile.as_deref(), cli)
+    }
+
+    /// Splits into file selection and the flag/env layer.
+    fn into_parts(self) -> (ConfigArgs, Settings) {
+        let cli = Settings {
+            sock: self.sock,
+            endpoint: self.endpoint,
+            batch_size: self.batch_size,
+            batch_ms: self.batch_ms,
+            chan_capacity: self.chan_capacity,
+            batch_bytes: self.batch_bytes,
+            spool_bytes: self.spool_bytes,
+            spool_dir: self.spool_dir,
+            span_timeout_secs: self.span_timeout_secs,
+            capture_messages: self.capture_messages,
+            capture_max_chars: self.capture_max_chars,
+            ..self.key.into_settings()
+        };
+        (self.files, cli)
+    }
+}
+
+/// Locates a running agent's sockets.
+#[derive(Args)]
+struct ControlArgs {
+    #[command(flatten)]
+    files: ConfigArgs,
+
+    /// Event socket path; the control socket is `<sock>.ctl` [default: /tmp/talon.sock]
+    #[arg(long, env = "TALON_SOCK")]
+    sock: Option<String>,
+}
+
+impl ControlArgs {
+    /// Resolves the socket path through the same layers `start` uses.
+    fn sock(self) -> Result<String> {
+        let cli = Settings {
+            sock: self.sock,
+            ..Default::default()
+        };
+        let loaded = config::load(
+            self.files.config.as_deref(),
+            self.files.profile.as_deref(),
+            cli,
+        )?;
+        Ok(loaded.settings.sock.unwrap_or_default())
+    }
+}
+
 #[derive(Subcommand)]
 enum Cmd {
     /// Start the agent daemon
     Start {
-        #[arg(long, default_value = "/tmp/talon.sock")]
-        sock: String,
+        #[command(flatten)]
+        args: AgentArgs,
+    },
+
+    /// Manually flush spooled events
+    Flush {
+        #[command(flatten)]
+        files: ConfigArgs,
 
         #[arg(long, env = "TRACE_ENDPOINT")]
-        endpoint: String,
+        endpoint: Option<String>,
+
+        #[command(flatten)]
+        key: KeyArgs,
 
-        #[arg(long, env = "TRACE_API_KEY")]
-        api_key: Option<String>,
+        #[arg(long, env = "TALON_SPOOL_DIR")]
+        spool_dir: Option<PathBuf>,
+    },
 
-        #[arg(long, default_value_t = 100)]
-        batch_size: usize,
+    /// Show config, uptime, counters, spool backlog and destination health
+    Status {
+        #[command(flatten)]
+        ctl: ControlArgs,
 
-        #[arg(long, default_value_t = 200)]
-        batch_ms: u64,
+        /// Print the raw JSON response
+        #[arg(long)]
+        json: bool,
+    },
 
-        #[arg(long, default_value_t = 10_000)]
-        chan_capacity: usize,
+    /// Flush all pending events, then stop the agent
+    Stop {
+        #[command(flatten)]
+        ctl: ControlArgs,
+    },
 
-        #[arg(long, default_value_t = 1_048_576)]
-        batch_bytes: usize,
+    /// Pause sending; batches spool to disk until resumed
+    Pause {
+        #[command(flatten)]
+        ctl: ControlArgs,
+    },
 
-        #[arg(long, default_value_t = 50_000_000)]
-        spool_bytes: u64,
+    /// Resume sending and drain the spool
+    Resume {
+        #[command(flatten)]
+        ctl: ControlArgs,
+    },
 
-        #[arg(long)]
-        spool_dir: Option<PathBuf>,
+    /// Re-read the config file and apply it (same as sending SIGHUP)
+    Reload {
+        #[command(flatten)]
+        ctl: ControlArgs,
     },
 
-    /// Manually flush spooled events
-    Flush {
-        #[arg(long, env = "TRACE_ENDPOINT")]
-        endpoint: String,
+    /// Stream frames as the agent processes them (raw, TraceV1 and outgoing)
+    Tail {
+        #[command(flatten)]
+        ctl: ControlArgs,
+
+        /// Only show this event type (hook name or canonical, e.g. PostToolUse or tool.post)
+        #[arg(long)]
+        event: Option<String>,
 
-        #[arg(long, env = "TRACE_API_KEY")]
-        api_key: Option<String>,
+        /// Only show this session
+        #[arg(long)]
+        session_id: Option<String>,
 
+        /// Only show this tool (e.g. Bash)
         #[arg(long)]
-        spool_dir: Option<PathBuf>,
+        tool: Option<String>,
+
+        #[arg(long, value_enum, default_value_t = TailFormat::Pretty)]
+        format: TailFormat,
+    },
+
+    /// Inspect agent configuration
+    Config {
+        #[command(subcommand)]
+        cmd: ConfigCmd,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCmd {
+    /// Print the effective settings after merging flags, env, file and defaults
+    Show {
+        #[command(flatten)]
+        args: AgentArgs,
     },
 }
 
+/// Output format for `talon-agent tail`.
+#[derive(Clone, Copy, ValueEnum)]
+enum TailFormat {
+    /// Header line plus indented JSON for each stage
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.cmd {
-        Cmd::Start {
-            sock,
-            endpoint,
-            api_key,
-            batch_size,
-            batch_ms,
-            chan_capacity,
-            batch_bytes,
-            spool_bytes,
-            spool_dir,
-        } => {
-            let spool_dir = spool_dir.unwrap_or(default_spool_dir()?);
-            fs::create_dir_all(&spool_dir).ok();
-
-            let config = Config {
-                endpoint,
-                api_key,
-                batch_size,
-                batch_ms,
-                chan_capacity,
-                batch_bytes,
-                spool_dir,
-                spool_bytes,
+        Cmd::Start { args } => {
+            let (files, cli) = args.into_parts();
+            let loaded = config::load(
+                files.config.as_deref(),
+                files.profile.as_deref(),
+                cli.clone(),
+            )?;
+            let config = loaded.settings.clone().into_config()?;
+            fs::create_dir_all(&config.spool_dir).ok();
+
+            let reloader = Reloader {
+                files,
+                cli,
+                applied: Mutex::new(loaded.settings),
+                sock: config.sock.clone(),
+                chan_capacity: config.chan_capacity,
             };
 
             #[cfg(unix)]
-            return run_unix(sock, config);
+            return run_unix(config, reloader);
 
             #[cfg(not(unix))]
-            return run_tcp("127.0.0.1:7878".to_string(), config);
+            return run_tcp("127.0.0.1:7878".to_string(), config, reloader);
         }
 
         Cmd::Flush {
+            files,
             endpoint,
-            api_key,
+            key,
             spool_dir,
         } => {
-            let spool_dir = spool_dir.unwrap_or(default_spool_dir()?);
+            let cli = Settings {
+                endpoint,
+                spool_dir,
+                ..key.into_settings()
+            };
+            let config = config::load(files.config.as_deref(), files.profile.as_deref(), cli)?
+                .settings
+                .into_config()?;
             let client = http_client()?;
-            flush_spool(&client, &endpoint, api_key.as_deref(), &spool_dir)?;
+            flush_spool(
+                &client,
+                &config.endpoint,
+                config.api_key()?.as_deref(),
+                &config.beak,
+                &config.spool_dir,
+            )?;
+            Ok(())
+        }
+
+        Cmd::Status { ctl, json } => {
+            let resp = control::request(&ctl.sock()?, Request::Status)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&resp)?);
+            } else {
+                print_status(&resp);
+            }
+            Ok(())
+        }
+
+        Cmd::Stop { ctl } => control_command(&ctl.sock()?, Request::Drain, "stopped"),
+        Cmd::Pause { ctl } => control_command(&ctl.sock()?, Request::Pause, "paused"),
+        Cmd::Resume { ctl } => control_command(&ctl.sock()?, Request::Resume, "resumed"),
+
+        Cmd::Reload { ctl } => {
+            let resp = control::request(&ctl.sock()?, Request::Reload)?;
+            if resp.get("ok").and_then(|v| v.as_bool()) != Some(true) {
+                let err = resp
+                    .get("error")
+                    .and_then(|e| e.as_str())
+                    .unwrap_or("unknown error");
+                return Err(anyhow::anyhow!(
+                    "reload rejected, agent kept its config: {err}"
+                ));
+            }
+            let list = |key: &str| -> Vec<String> {
+                resp[key]
+                    .as_array()
+                    .map(|a| {
+                        a.iter()
+                            .filter_map(|v| v.as_str().map(String::from))
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            };
+            let changed = list("changed");
+            if changed.is_empty() {
+                println!("talon-agent reloaded (no changes)");
+            } else {
+                println!("talon-agent reloaded: {}", changed.join(", "));
+            }
+            for warning in list("warnings") {
+                println!("warning: {warning}");
+            }
+            Ok(())
+        }
+
+        Cmd::Tail {
+            ctl,
+            event,
+            session_id,
+            tool,
+            format,
+        } => {
+            let filter = TailFilter {
+                event,
+                session_id,
+                tool,
+            };
+            let mut out = std::io::stdout().lock();
+            for frame in control::subscribe(&ctl.sock()?, filter)? {
+                let text = match format {
+                    TailFormat::Pretty => render_pretty(&frame),
+                    TailFormat::Json => format!("{}\n", serde_json::to_string(&frame)?),
+                };
+                // Stop quietly when piped into `head` and the reader goes away.
+                if out
+                    .write_all(text.as_bytes())
+                    .and_then(|_| out.flush())
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            Ok(())
+        }
+
+        Cmd::Config {
+            cmd: ConfigCmd::Show { args },
+        } => {
+            let loaded = args.load()?;
+            match &loaded.path {
+                Some(p) => println!("# config file: {}", p.display()),
+                None => println!("# config file: none"),
+            }
+            if let Some(profile) = &loaded.profile {
+                println!("# profile: {profile}");
+            }
+            if loaded.settings.endpoint.is_none() {
+                println!("# warning: no endpoint configured; `start` will fail");
+            }
+            print!("{}", toml::to_string(&loaded.settings.redacted())?);
             Ok(())
         }
     }
 }
 
+/// Sends a control request and reports the outcome on stdout.
+fn control_command(sock: &str, req: Request, done: &str) -> Result<()> {
+    let resp = control::request(sock, req)?;
+    if resp.get("ok").and_then(|v| v.as_bool()) != Some(true) {
+        let err = resp
+            .get("error")
+            .and_then(|e| e.as_str())
+            .unwrap_or("unknown error");
+        return Err(anyhow::anyhow!("agent refused request: {err}"));
+    }
+    println!("talon-agent {done}");
+    Ok(())
+}
+
+/// Renders a `status` response for humans.
+fn print_status(resp: &Json) {
+    let s = |v: &Json| match v {
+        Json::String(s) => s.clone(),
+        Json::Null => "-".to_string(),
+        other => other.to_string(),
+    };
+    let state = if resp["paused"].as_bool() == Some(true) {
+        "paused"
+    } else {
+        "running"
+    };
+
+    println!(
+        "talon-agent {} (pid {}, {state})",
+        s(&resp["version"]),
+        s(&resp["pid"])
+    );
+    println!("  uptime:      {}s", s(&resp["uptime_secs"]));
+    println!("  endpoint:    {}", s(&resp["config"]["endpoint"]));
+    println!("  api key:     {}", s(&resp["config"]["api_key_source"]));
+    println!("  socket:      {}", s(&resp["config"]["sock"]));
+    println!(
+        "  spool:       {} events, {} bytes",
+        s(&resp["spool"]["events"]),
+        s(&resp["spool"]["bytes"])
+    );
+
+    let dest = &resp["destination"];
+    let health = if dest["healthy"].as_bool() == Some(true) {
+        "healthy"
+    } else {
+        "failing"
+    };
+    println!(
+        "  destination: {health} (last success {})",
+        s(&dest["last_success"])
+    );
+    if let Some(err) = dest.get("last_error").filter(|e| !e.is_null()) {
+        println!("  last error:  {} at {}", s(&err["error"]), s(&err["at"]));
+    }
+
+    println!("  counters:");
+    if let Some(counters) = resp["counters"].as_object() {
+        for (name, value) in counters {
+            println!("    {name:<15} {value}");
+        }
+    }
+}
+
+/// Re-resolves configuration for a running agent.
+///
+/// Keeps the flag/env layer captured at startup and re-reads the config file, so a
+/// reload behaves exactly like a restart with the same command line. The socket
+/// path and channel capacity are fixed for the life of the process; changes to
+/// them are reported as warnings and ignored.
+struct Reloader {
+    files: ConfigArgs,
+    cli: Settings,
+    /// Merged settings currently in effect, used to report what changed.
+    applied: Mutex<Settings>,
+    sock: String,
+    chan_capacity: usize,
+}
+
+impl Reloader {
+    /// Validates and applies the current config file.
+    ///
+    /// On any error the running configuration is left untouched and the error is
+    /// logged, recorded for `status`, and returned to the caller.
+    fn reload(&self, state: &AgentState, loop_tx: &chan::Sender<LoopCmd>) -> Json {
+        let loaded = config::load(
+            self.files.config.as_deref(),
+            self.files.profile.as_deref(),
+            self.cli.clone(),
+        );
+        let (mut settings, mut new) =
+            match loaded.and_then(|l| Ok((l.settings.clone(), l.settings.into_config()?))) {
+                Ok(v) => v,
+                Err(e) => {
+                    let msg = format!("{e:#}");
+                    eprintln!("talon-agent: reload failed, keeping current config: {msg}");
+                    state.record_reload(Err(msg.clone()));
+                    return control::error_response(msg);
+                }
+            };
+
+        // Fixed settings keep their running values in both the config and the
+        // settings, so they are neither reported as changed nor recorded as applied.
+        let mut applied = self.applied.lock().unwrap_or_else(|e| e.into_inner());
+        let mut warnings = Vec::new();
+        if new.sock != self.sock {
+            warnings.push(format!("sock change to {} requires a restart", new.sock));
+            new.sock = self.sock.clone();
+            settings.sock = applied.sock.clone();
+        }
+        if new.chan_capacity != self.chan_capacity {
+            warnings.push("chan_capacity change requires a restart".to_string());
+            new.chan_capacity = self.chan_capacity;
+            settings.chan_capacity = applied.chan_capacity;
+        }
+        let changed = changed_keys(&applied, &settings);
+        let (summary, spool_dir) = (new.summary(), new.spool_dir.clone());
+        fs::create_dir_all(&spool_dir).ok();
+
+        let resp = control::forward(loop_tx, |reply| LoopCmd::Reload(Box::new(new), reply));
+        if resp.get("ok").and_then(|v| v.as_bool()) != Some(true) {
+            return resp;
+        }
+        *applied = settings;
+        state.set_config(summary, spool_dir);
+        state.record_reload(Ok(()));
+        eprintln!(
+            "talon-agent: config reloaded (changed: {})",
+            changed.join(", ")
+        );
+        for warning in &warnings {
+            eprintln!("talon-agent: reload warning: {warning}");
+        }
+
+        serde_json::json!({ "ok": true, "changed": changed, "warnings": warnings })
+    }
+}
+
+/// Names of settings whose values differ. Values are not reported, so secrets
+/// don't leak into logs.
+fn changed_keys(old: &Settings, new: &Settings) -> Vec<String> {
+    let (old, new) = (
+        serde_json::to_value(old).unwrap_or_default(),
+        serde_json::to_value(new).unwrap_or_default(),
+    );
+    let empty = serde_json::Map::new();
+    let (old, new) = (
+        old.as_object().unwrap_or(&empty),
+        new.as_object().unwrap_or(&empty),
+    );
+    let mut keys: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|k| old.get(*k) != new.get(*k))
+        .cloned()
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
 /// Run agent with Unix socket listener.
 ///
 /// Uses Unix domain sockets for better security (filesystem permissions) and lower
-/// overhead than TCP. Socket secured with 0o600 permissions.
+/// overhead than TCP. Socket secured with 0o600 permissions. A second socket at
+/// `<sock>.ctl` serves control requests (see [`control`]), and SIGHUP reloads
+/// the configuration.
 #[cfg(unix)]
-fn run_unix(sock: String, config: Config) -> Result<()> {
+fn run_unix(config: Config, reloader: Reloader) -> Result<()> {
     use std::os::unix::net::UnixListener;
 
+    let sock = config.sock.clone();
+
     // Clean up stale socket
     let _ = fs::remove_file(&sock);
     let listener = UnixListener::bind(&sock).with_context(|| format!("bind UDS {}", sock))?;
 
-    // Secure socket: owner read-write only
+    let ctl_path = control::control_path(&sock);
+    let _ = fs::remove_file(&ctl_path);
+    let ctl_listener =
+        UnixListener::bind(&ctl_path).with_context(|| format!("bind UDS {}", ctl_path))?;
+
+    // Secure sockets: owner read-write only
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
         fs::set_permissions(&sock, fs::Permissions::from_mode(0o600)).ok();
+        fs::set_permissions(&ctl_path, fs::Permissions::from_mode(0o600)).ok();
     }
 
+    let state = Arc::new(AgentState::new(config.summary(), config.spool_dir.clone()));
     let (tx, rx) = chan::bounded::<String>(config.chan_capacity);
+    let (loop_tx, loop_rx) = chan::unbounded::<LoopCmd>();
     let client = http_client()?;
 
     // Spawn HTTP sender thread
-    thread::spawn(move || http_loop(rx, client, config));
+    let loop_state = Arc::clone(&state);
+    thread::spawn(move || http_loop(rx, loop_rx, client, config, loop_state));
+
+    // Drain unlinks both sockets first so taps stop connecting mid-drain. A tap
+    // that finds none starts a new agent, whose sockets this one must not remove
+    // on exit.
+    let reloader = Arc::new(reloader);
+    let ctl = Arc::new(control::Control {
+        state: Arc::clone(&state),
+        loop_tx: loop_tx.clone(),
+        on_drain: {
+            let ctl_path = ctl_path.clone();
+            Box::new(move || {
+                let _ = fs::remove_file(&sock);
+                let _ = fs::remove_file(&ctl_path);
+            })
+        },
+        reload: {
+            let (reloader, state, loop_tx) =
+                (Arc::clone(&reloader), Arc::clone(&state), loop_tx.clone());
+            Box::new(move || reloader.reload(&state, &loop_tx))
+        },
+    });
+
+    // One thread per control connection, since `tail` subscriptions stay open.
+    thread::spawn(move || {
+        for stream in ctl_listener.incoming().flatten() {
+            let ctl = Arc::clone(&ctl);
+            thread::spawn(move || {
+                if control::handle_conn(stream, &ctl) {
+                    std::process::exit(0);
+                }
+            });
+        }
+    });
+
+    // SIGHUP triggers the same reload as the control command.
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])
+        .context("install SIGHUP handler")?;
+    let hup_state = Arc::clone(&state);
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            reloader.reload(&hup_state, &loop_tx);
+        }
+    });
 
     // Accept connections
     for stream in listener.incoming().flatten() {
         let txc = tx.clone();
-        thread::spawn(move || handle_conn_unix(stream, txc));
+        let conn_state = Arc::clone(&state);
+        thread::spawn(move || handle_conn_unix(stream, txc, &conn_state));
     }
 
     Ok(())
@@ -210,21 +838,50 @@ fn run_unix(sock: String, config: Config) -> Result<()> {
 
 /// Run agent with TCP listener (Windows fallback).
 ///
-/// Binds to localhost (127.0.0.1) to reduce security risks.
+/// Binds to localhost (127.0.0.1) to reduce security risks. Control requests are
+/// served on [`control::CONTROL_ADDR`].
 #[cfg(not(unix))]
-fn run_tcp(addr: String, config: Config) -> Result<()> {
+fn run_tcp(addr: String, config: Config, reloader: Reloader) -> Result<()> {
     use std::net::TcpListener;
 
     let listener = TcpListener::bind(&addr).with_context(|| format!("bind TCP {}", addr))?;
+    let ctl_listener = TcpListener::bind(control::CONTROL_ADDR)
+        .with_context(|| format!("bind TCP {}", control::CONTROL_ADDR))?;
+
+    let state = Arc::new(AgentState::new(config.summary(), config.spool_dir.clone()));
     let (tx, rx) = chan::bounded::<String>(config.chan_capacity);
+    let (loop_tx, loop_rx) = chan::unbounded::<LoopCmd>();
     let client = http_client()?;
 
-    thread::spawn(move || http_loop(rx, client, config));
+    let loop_state = Arc::clone(&state);
+    thread::spawn(move || http_loop(rx, loop_rx, client, config, loop_state));
+
+    let ctl = Arc::new(control::Control {
+        state: Arc::clone(&state),
+        loop_tx: loop_tx.clone(),
+        on_drain: Box::new(|| {}),
+        reload: {
+            let state = Arc::clone(&state);
+            Box::new(move || reloader.reload(&state, &loop_tx))
+        },
+    });
+
+    thread::spawn(move || {
+        for stream in ctl_listener.incoming().flatten() {
+            let ctl = Arc::clone(&ctl);
+            thread::spawn(move || {
+                if control::handle_conn(stream, &ctl) {
+                    std::process::exit(0);
+                }
+            });
+        }
+    });
 
     for stream in listener.incoming() {
         if let Ok(stream) = stream {
             let txc = tx.clone();
-            thread::spawn(move || handle_conn_tcp(stream, txc));
+            let conn_state = Arc::clone(&state);
+            thread::spawn(move || handle_conn_tcp(stream, txc, &conn_state));
         }
     }
 
@@ -236,10 +893,15 @@ fn run_tcp(addr: String, config: Config) -> Result<()> {
 /// Reads line-delimited JSON frames and forwards to the batching channel.
 /// Blocks on channel send to apply backpressure.
 #[cfg(unix)]
-fn handle_conn_unix(stream: std::os::unix::net::UnixStream, tx: chan::Sender<String>) {
+fn handle_conn_unix(
+    stream: std::os::unix::net::UnixStream,
+    tx: chan::Sender<String>,
+    state: &AgentState,
+) {
     let reader = BufReader::new(stream);
     for line in reader.lines().map_while(Result::ok) {
         if !line.trim().is_empty() {
+            state.counters.received.fetch_add(1, Ordering::Relaxed);
             // Block on send to apply backpressure
             let _ = tx.send(line);
         }
@@ -250,10 +912,11 @@ fn handle_conn_unix(stream: std::os::unix::net::UnixStream, tx: chan::Sender<Str
 ///
 /// Same behavior as Unix socket handler but over TCP.
 #[cfg(not(unix))]
-fn handle_conn_tcp(stream: std::net::TcpStream, tx: chan::Sender<String>) {
+fn handle_conn_tcp(stream: std::net::TcpStream, tx: chan::Sender<String>, state: &AgentState) {
     let reader = BufReader::new(stream);
     for line in reader.lines().flatten() {
         if !line.trim().is_empty() {
+            state.counters.received.fetch_add(1, Ordering::Relaxed);
             let _ = tx.send(line);
         }
     }
@@ -277,74 +940,264 @@ fn http_client() -> Result<reqwest::blocking::Client> {
 /// - **Time trigger**: `batch_ms` elapsed
 ///
 /// Failed sends spool to disk for retry. Malformed events quarantine for debugging.
-/// After successful sends, attempts to drain spooled events.
-fn http_loop(rx: chan::Receiver<String>, client: reqwest::blocking::Client, config: Config) {
-    let mut buf: Vec<Json> = Vec::with_capacity(config.batch_size);
-    let mut buf_bytes: usize = 0;
+/// After successful sends, attempts to drain spooled events. While paused, due
+/// batches go straight to the spool.
+///
+/// Also services [`LoopCmd`]s from the control thread, since only this loop may
+/// touch the batch buffer. A reload swaps `config` in place, so the channel and
+/// the in-memory batch survive it.
+fn http_loop(
+    rx: chan::Receiver<String>,
+    cmds: chan::Receiver<LoopCmd>,
+    client: reqwest::blocking::Client,
+    mut config: Config,
+    state: Arc<AgentState>,
+) {
+    let mut batch = Batch::with_capacity(config.batch_size);
     let mut last = Instant::now();
 
     // Try to drain any existing spooled events from previous runs
-    let _ = flush_spool(
-        &client,
-        &config.endpoint,
-        config.api_key.as_deref(),
-        &config.spool_dir,
-    );
+    drain_spool(&client, &config, &state);
 
-    let timeout = Duration::from_millis(config.batch_ms);
+    let mut timeout = Duration::from_millis(config.batch_ms);
 
     loop {
-        match rx.recv_timeout(timeout) {
-            Ok(line) => {
-                // Parse tap frame -> map to canonical TraceV1 -> push to batch buffer
-                match serde_json::from_str::<Json>(&line) {
-                    Ok(frame) => match from_tap_frame(frame) {
-                        Ok(mut rec) => {
-                            canonicalize(&mut rec);
-                            let json_rec = serde_json::to_value(&rec)
-                                .unwrap_or_else(|_| Json::Object(Default::default()));
-                            let sz = json_rec.to_string().len();
-                            buf.push(json_rec);
-                            buf_bytes += sz;
+        chan::select! {
+            recv(rx) -> msg => match msg {
+                Ok(line) => batch.ingest(&line, &config, &state),
+                Err(_) => break,
+            },
+            recv(cmds) -> cmd => match cmd {
+                Ok(LoopCmd::Flush(reply)) => {
+                    let events = batch.len();
+                    flush_batch(&client, &config, &state, &mut batch);
+                    last = Instant::now();
+                    let _ = reply.send(serde_json::json!({ "ok": true, "flushed": events }));
+                }
+                Ok(LoopCmd::Drain(reply)) => {
+                    // Pull in everything taps already queued, then push it all out,
+                    // including tool starts that will never see their end now.
+                    for line in rx.try_iter() {
+                        batch.ingest(&line, &config, &state);
+                    }
+                    batch.drain_spans(&config, &state);
+                    let events = batch.len();
+                    flush_batch(&client, &config, &state, &mut batch);
+                    let _ = reply.send(serde_json::json!({ "ok": true, "flushed": events }));
+                    return;
+                }
+                Ok(LoopCmd::Reload(new, reply)) => {
+                    config = *new;
+                    timeout = Duration::from_millis(config.batch_ms);
+                    let _ = reply.send(serde_json::json!({ "ok": true }));
+                }
+                Err(_) => {}
+            },
+            default(timeout) => {}
+        }
+
+        batch.expire_spans(&config, &state);
+
+        // Check if any of the three flush triggers have fired
+        let time_due = last.elapsed() >= timeout && !batch.is_empty();
+        let size_due = batch.len() >= config.batch_size || batch.bytes >= config.batch_bytes;
+
+        if time_due || size_due {
+            flush_batch(&client, &config, &state, &mut batch);
+            last = Instant::now();
+        }
+    }
+}
+
+/// In-memory batch of canonical TraceV1 events awaiting delivery.
+///
+/// Also owns the tool span tracker, which outlives individual batches: a held
+/// tool start is emitted into whichever batch is open when its end arrives. The
+/// session rollups outlive batches the same way.
+struct Batch {
+    events: Vec<Json>,
+    bytes: usize,
+    spans: SpanTracker,
+    usage: UsageLedger,
+    rollup: SessionRollup,
+    last_expiry: Instant,
+}
+
+impl Batch {
+    fn with_capacity(n: usize) -> Self {
+        Self {
+            events: Vec::with_capacity(n),
+            bytes: 0,
+            spans: SpanTracker::default(),
+            usage: UsageLedger::default(),
+            rollup: SessionRollup::default(),
+            last_expiry: Instant::now(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Parse tap frame -> map to canonical TraceV1 -> push to batch buffer.
+    ///
+    /// Frames that fail to parse or map are quarantined instead. Every outcome is
+    /// published to `tail` subscribers, if any.
+    fn ingest(&mut self, line: &str, config: &Config, state: &AgentState) {
+        let quarantine = |raw: &Json, reason: String| {
+            state.counters.quarantined.fetch_add(1, Ordering::Relaxed);
+            state.tail.publish_error(raw, &reason);
+            let _ = append_to_quarantine(&config.spool_dir, line, reason);
+        };
+
+        match serde_json::from_str::<Json>(line) {
+            Ok(frame) => {
+                // Only keep a copy of the raw frame when someone is tailing.
+                let raw = state.tail.is_active().then(|| frame.clone());
+                let raw = raw.as_ref().unwrap_or(&Json::Null);
+                match from_tap_frame(frame, &config.map) {
+                    Ok(rec) => {
+                        state.counters.mapped.fetch_add(1, Ordering::Relaxed);
+                        let held = state.tail.is_active().then(|| rec.clone());
+                        let out = self.spans.process(rec, Instant::now());
+                        if out.is_empty()
+                            && let Some(held) = held
+                        {
+                            state.tail.publish_held(raw, &held);
                         }
-                        Err(e) => {
-                            let _ = append_to_quarantine(&config.spool_dir, &line, e.to_string());
+                        for rec in out {
+                            self.push(rec, raw, config, state);
                         }
-                    },
-                    Err(e) => {
-                        let _ = append_to_quarantine(
-                            &config.spool_dir,
-                            &line,
-                            format!("parse error: {e}"),
-                        );
                     }
+                    Err(e) => quarantine(raw, e.to_string()),
                 }
             }
-            Err(chan::RecvTimeoutError::Timeout) => {}
-            Err(chan::RecvTimeoutError::Disconnected) => break,
+            Err(e) => quarantine(&Json::String(line.to_string()), format!("parse error: {e}")),
         }
+    }
 
-        // Check if any of the three flush triggers have fired
-        let time_due = last.elapsed() >= timeout && !buf.is_empty();
-        let size_due = buf.len() >= config.batch_size || buf_bytes >= config.batch_bytes;
+    /// Attributes usage, prices it, canonicalizes the trace and appends it to the batch.
+    ///
+    /// Costs are computed after attribution so repeated message usage isn't billed twice.
+    /// Events ending a turn or session are followed by their session's summary.
+    fn push(
+        &mut self,
+        mut rec: crate::schema::TraceV1,
+        raw: &Json,
+        config: &Config,
+        state: &AgentState,
+    ) {
+        self.usage.attribute(&mut rec);
+        config.pricing.apply(&mut rec);
+        canonicalize(&mut rec);
+        let summary = self.rollup.observe(&rec);
+        if config.processors.keeps(&rec) {
+            config.processors.apply(&mut rec);
+            state.tail.publish_trace(raw, &rec, &config.beak);
+            let json_rec =
+                serde_json::to_value(&rec).unwrap_or_else(|_| Json::Object(Default::default()));
+            self.bytes += json_rec.to_string().len();
+            self.events.push(json_rec);
+        } else {
+            state.tail.publish_dropped(raw, &rec);
+        }
+        if let Some(summary) = summary {
+            self.push(summary, &Json::Null, config, state);
+        }
+    }
 
-        if time_due || size_due {
-            if send_batch(&client, &config.endpoint, config.api_key.as_deref(), &buf).is_err() {
+    /// Emits tool starts and turns that timed out waiting for their end. Checked at most
+    /// once a second.
+    fn expire_spans(&mut self, config: &Config, state: &AgentState) {
+        if self.last_expiry.elapsed() < Duration::from_secs(1) || self.spans.is_idle() {
+            return;
+        }
+        self.last_expiry = Instant::now();
+        let max_age = Duration::from_secs(config.span_timeout_secs);
+        for rec in self.spans.expire(Instant::now(), max_age) {
+            self.push(rec, &Json::Null, config, state);
+        }
+    }
+
+    /// Emits every held tool start and open turn as incomplete.
+    fn drain_spans(&mut self, config: &Config, state: &AgentState) {
+        for rec in self.spans.drain() {
+            self.push(rec, &Json::Null, config, state);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.events.clear();
+        self.bytes = 0;
+    }
+}
+
+/// Sends the batch (or spools it when paused or on failure), then drains the spool.
+fn flush_batch(
+    client: &reqwest::blocking::Client,
+    config: &Config,
+    state: &AgentState,
+    batch: &mut Batch,
+) {
+    if !batch.is_empty() {
+        let result = if state.is_paused() {
+            Err(anyhow::anyhow!("sending paused"))
+        } else {
+            config.api_key().and_then(|key| {
+                send_batch(
+                    client,
+                    &config.endpoint,
+                    key.as_deref(),
+                    &config.beak,
+                    &batch.events,
+                )
+            })
+        };
+
+        match result {
+            Ok(()) => state.record_success(batch.len()),
+            Err(e) => {
+                if !state.is_paused() {
+                    config.on_send_error(&e);
+                    state.record_failure(&e);
+                }
                 // On failure, spool to disk for later retry
-                let _ = append_to_spool(&config.spool_dir, &buf, config.spool_bytes);
+                if append_to_spool(&config.spool_dir, &batch.events, config.spool_bytes).is_ok() {
+                    state
+                        .counters
+                        .spooled
+                        .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                }
             }
-            buf.clear();
-            buf_bytes = 0;
-            last = Instant::now();
-
-            // Opportunistically drain spool after successful send
-            let _ = flush_spool(
-                &client,
-                &config.endpoint,
-                config.api_key.as_deref(),
-                &config.spool_dir,
-            );
         }
+        batch.clear();
+    }
+
+    // Opportunistically drain spool after successful send
+    drain_spool(client, config, state);
+}
+
+/// Drains the spool unless sending is paused, recording failures for `status`.
+fn drain_spool(client: &reqwest::blocking::Client, config: &Config, state: &AgentState) {
+    if state.is_paused() {
+        return;
+    }
+    let result = config.api_key().and_then(|key| {
+        flush_spool(
+            client,
+            &config.endpoint,
+            key.as_deref(),
+            &config.beak,
+            &config.spool_dir,
+        )
+    });
+    if let Err(e) = result {
+        config.on_send_error(&e);
+        state.record_failure(&e);
     }
 }
 
@@ -362,6 +1215,7 @@ fn send_batch(
     client: &reqwest::blocking::Client,
     endpoint: &str,
     api_key: Option<&str>,
+    beak: &BeakOptions,
     events: &[Json],
 ) -> Result<()> {
     if events.is_empty() {
@@ -377,7 +1231,7 @@ fn send_batch(
             // Deserialize Json back to TraceV1 for transformation
             serde_json::from_value::<crate::schema::TraceV1>(event.clone()).ok()
         })
-        .map(|trace| to_beak_format(&trace))
+        .map(|trace| to_beak_format(&trace, beak))
         .collect();
 
     // Serialize and compress (typically 5-10x size reduction)
@@ -400,6 +1254,14 @@ fn send_batch(
     for attempt in 0..4 {
         match req.try_clone().unwrap().body(body_gz.clone()).send() {
             Ok(resp) if resp.status().is_success() => return Ok(()),
+            Ok(resp)
+                if matches!(
+                    resp.status(),
+                    reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+                ) =>
+            {
+                return Err(AuthRejected(resp.status()).into());
+            }
             Ok(resp) if resp.status().is_client_error() => {
                 // Don't retry 4xx - client errors won't resolve on retry
                 return Err(anyhow::anyhow!("collector returned 4xx: {}", resp.status()));
@@ -549,6 +1411,7 @@ fn flush_spool(
     client: &reqwest::blocking::Client,
     endpoint: &str,
     api_key: Option<&str>,
+    beak: &BeakOptions,
     dir: &Path,
 ) -> Result<()> {
     let file_path = dir.join("events.jsonl");
@@ -567,14 +1430,14 @@ fn flush_spool(
         if let Ok(val) = serde_json::from_str::<Json>(&line) {
             batch.push(val);
             if batch.len() >= 500 {
-                send_batch(client, endpoint, api_key, &batch)?;
+                send_batch(client, endpoint, api_key, beak, &batch)?;
                 batch.clear();
             }
         }
     }
 
     if !batch.is_empty() {
-        send_batch(client, endpoint, api_key, &batch)?;
+        send_batch(client, endpoint, api_key, beak, &batch)?;
     }
 
     // Clear spool file only after all events successfully sent
@@ -776,7 +1639,13 @@ mod tests {
 
         // Flush spool
         let client = http_client().unwrap();
-        let result = flush_spool(&client, &mock_server.url(), None, temp_dir.path());
+        let result = flush_spool(
+            &client,
+            &mock_server.url(),
+            None,
+            &BeakOptions::default(),
+            temp_dir.path(),
+        );
 
         assert!(result.is_ok(), "flush_spool failed: {:?}", result.err());
 
@@ -810,7 +1679,13 @@ mod tests {
 
         // Flush spool
         let client = http_client().unwrap();
-        let result = flush_spool(&client, &mock_server.url(), None, temp_dir.path());
+        let result = flush_spool(
+            &client,
+            &mock_server.url(),
+            None,
+            &BeakOptions::default(),
+            temp_dir.path(),
+        );
 
         assert!(result.is_ok());
 
@@ -829,7 +1704,7 @@ mod tests {
 
             // Jitter should be ±50%: 100ms to 300ms
             assert!(
-                ms >= 100 && ms <= 300,
+                (100..=300).contains(&ms),
                 "Jittered delay {}ms out of expected range [100, 300]",
                 ms
             );
@@ -853,7 +1728,7 @@ mod tests {
         let content = std::fs::read_to_string(quarantine_file).unwrap();
 
         // Verify format
-        let entry: Json = serde_json::from_str(&content.trim()).unwrap();
+        let entry: Json = serde_json::from_str(content.trim()).unwrap();
         assert_eq!(entry["reason"], "parse error");
         assert_eq!(entry["raw"], "{invalid json}");
     }
@@ -868,6 +1743,143 @@ mod tests {
         assert!(path.to_string_lossy().contains("spool"));
     }
 
+    #[test]
+    fn test_reload_applies_valid_config_and_keeps_old_on_error() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("config.toml");
+        let write = |endpoint: &str, batch_size: usize, sock: &str| {
+            let body = format!(
+                "endpoint = \"{endpoint}\"\nbatch_size = {batch_size}\nsock = \"{sock}\"\nspool_dir = \"{}\"\n",
+                temp.path().join("spool").display()
+            );
+            fs::write(&path, body).unwrap();
+        };
+        write("http://a", 10, "/tmp/a.sock");
+
+        let files = ConfigArgs {
+            config: Some(path.clone()),
+            profile: None,
+        };
+        let loaded = config::load(Some(&path), None, Settings::default()).unwrap();
+        let config = loaded.settings.clone().into_config().unwrap();
+        let reloader = Reloader {
+            files,
+            cli: Settings::default(),
+            applied: Mutex::new(loaded.settings),
+            sock: config.sock.clone(),
+            chan_capacity: config.chan_capacity,
+        };
+        let state = AgentState::new(config.summary(), config.spool_dir.clone());
+
+        // Stand-in for http_loop: acknowledge reloads and report what was applied.
+        let (loop_tx, loop_rx) = chan::unbounded::<LoopCmd>();
+        let (seen_tx, seen_rx) = chan::unbounded::<Config>();
+        thread::spawn(move || {
+            for cmd in loop_rx {
+                if let LoopCmd::Reload(new, reply) = cmd {
+                    seen_tx.send(*new).unwrap();
+                    let _ = reply.send(serde_json::json!({ "ok": true }));
+                }
+            }
+        });
+
+        write("http://b", 20, "/tmp/b.sock");
+        let resp = reloader.reload(&state, &loop_tx);
+        assert_eq!(resp["ok"], true);
+        assert_eq!(
+            resp["changed"],
+            serde_json::json!(["batch_size", "endpoint"]),
+            "ignored sock change isn't reported as changed"
+        );
+        assert_eq!(resp["warnings"].as_array().unwrap().len(), 1);
+        let applied = seen_rx.try_recv().unwrap();
+        assert_eq!(applied.endpoint, "http://b");
+        assert_eq!(applied.batch_size, 20);
+        assert_eq!(applied.sock, "/tmp/a.sock", "sock is fixed until restart");
+        assert_eq!(
+            reloader.applied.lock().unwrap().sock.as_deref(),
+            Some("/tmp/a.sock"),
+            "applied settings match the running ones"
+        );
+
+        let resp = reloader.reload(&state, &loop_tx);
+        assert_eq!(resp["changed"], serde_json::json!([]));
+        assert_eq!(resp["warnings"].as_array().unwrap().len(), 1);
+        seen_rx.try_recv().unwrap();
+
+        fs::write(&path, "batch_size = 0\n").unwrap();
+        let resp = reloader.reload(&state, &loop_tx);
+        assert_eq!(resp["ok"], false);
+        assert!(
+            seen_rx.try_recv().is_err(),
+            "invalid config must not reach the loop"
+        );
+        assert_eq!(state.status()["last_reload"]["ok"], false);
+        assert_eq!(state.status()["config"]["endpoint"], "http://b");
+    }
+
+    #[test]
+    fn test_shipped_hooks_produce_a_final_session_summary() {
+        let shipped: Json = serde_json::from_str(include_str!(
+            "../../../../../examples/talon-observability/hooks/hooks.json"
+        ))
+        .unwrap();
+        let session = [
+            ("SessionStart", serde_json::json!({ "source": "startup" })),
+            (
+                "UserPromptSubmit",
+                serde_json::json!({ "prompt": "list files" }),
+            ),
+            (
+                "PreToolUse",
+                serde_json::json!({ "tool_name": "Bash", "tool_use_id": "t1", "tool_input": { "command": "ls" } }),
+            ),
+            (
+                "PostToolUse",
+                serde_json::json!({ "tool_name": "Bash", "tool_use_id": "t1", "tool_response": "a\nb" }),
+            ),
+            ("Stop", serde_json::json!({})),
+            ("SessionEnd", serde_json::json!({ "reason": "exit" })),
+        ];
+
+        let temp = TempDir::new().unwrap();
+        let settings = Settings {
+            endpoint: Some("http://localhost:1".to_string()),
+            spool_dir: Some(temp.path().to_path_buf()),
+            ..Default::default()
+        };
+        let config = settings.into_config().unwrap();
+        let state = AgentState::new(config.summary(), config.spool_dir.clone());
+        let mut batch = Batch::with_capacity(16);
+        for (hook, payload) in session {
+            assert!(shipped.get(hook).is_some(), "{hook} isn't registered");
+            let frame = serde_json::json!({
+                "event": hook,
+                "ts": "2025-11-13T10:30:00Z",
+                "env": { "session_id": "sess-1" },
+                "payload": payload
+            });
+            batch.ingest(&frame.to_string(), &config, &state);
+        }
+
+        let summary = batch
+            .events
+            .iter()
+            .rfind(|e| e["event"] == rollup::SUMMARY_EVENT)
+            .expect("a session summary");
+        let label = |key: &str| {
+            summary["labels"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|l| l["key"] == key)
+                .map(|l| l["value"].clone())
+        };
+        assert_eq!(label("session_summary_final"), Some(Json::from("true")));
+        assert_eq!(label("session_turns"), Some(Json::from("1")));
+        assert_eq!(label("session_tool_calls"), Some(Json::from("1")));
+    }
+
     /// Test that concurrent appends during rotation do NOT lose data.
     ///
     /// Verifies the fix for the race condition where:
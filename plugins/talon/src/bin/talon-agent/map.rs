@@ -8,12 +8,46 @@
 //! payload in extensions for audit purposes.
 
 use crate::schema::*;
+use crate::transcript::{message_text, truncate_chars};
 use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
 use serde_json::Value as Json;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
 use std::path::Path;
 
+/// How much of the conversation is copied into `inputs.messages_compact`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureMode {
+    /// Nothing (default)
+    #[default]
+    Off,
+    /// Roles and lengths only, no text
+    Metadata,
+    /// Roles and text, truncated per message; Bash command lines too
+    Content,
+}
+
+/// Mapping options that come from agent configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapOptions {
+    /// Message capture level for `agent.stop` events.
+    pub capture: CaptureMode,
+    /// Characters kept per captured message in [`CaptureMode::Content`].
+    pub max_chars: usize,
+    /// Whether events are labelled with the git context of their working directory.
+    pub git: bool,
+}
+
+impl Default for MapOptions {
+    fn default() -> Self {
+        Self {
+            capture: CaptureMode::Off,
+            max_chars: 2000,
+            git: true,
+        }
+    }
+}
+
 /// Safely converts a JSON value to u32, saturating at u32::MAX if the value exceeds the limit.
 ///
 /// This prevents silent truncation when token counts or latencies exceed 4,294,967,295.
@@ -66,29 +100,49 @@ fn expand_path(path: &str) -> String {
 /// - `None` if file doesn't exist, can't be read, or no valid messages found
 ///
 /// # Performance
-/// Reads entire file sequentially. Typically < 1MB for most sessions.
-/// Happens during batching window (200ms), not on critical path.
+/// Reads incrementally through the per-path cache in [`crate::transcript`]: only
+/// lines appended since the previous event are parsed.
 fn read_latest_assistant_message(transcript_path: &str) -> Option<Json> {
     let expanded_path = expand_path(transcript_path);
-    let file = File::open(Path::new(&expanded_path)).ok()?;
-    let reader = BufReader::new(file);
-
-    let mut latest: Option<Json> = None;
+    crate::transcript::read(Path::new(&expanded_path), |t| t.latest_assistant.clone())?
+}
 
-    for line in reader.lines() {
-        // Skip lines with I/O errors instead of aborting the scan
-        let Ok(line) = line else { continue };
+/// Returns the tool calls requested by an assistant message, with their status,
+/// and the message's latency (first block, last block) in milliseconds.
+fn read_message_details(
+    transcript_path: &str,
+    message_id: &str,
+) -> (Vec<ToolCall>, Option<(u32, u32)>) {
+    let expanded_path = expand_path(transcript_path);
+    crate::transcript::read(Path::new(&expanded_path), |t| {
+        (t.tool_calls(message_id), t.latency(message_id))
+    })
+    .unwrap_or_default()
+}
 
-        if let Ok(entry) = serde_json::from_str::<Json>(&line)
-            && entry.get("type").and_then(|t| t.as_str()) == Some("assistant")
-            && let Some(msg) = entry.get("message")
-            && msg.get("usage").is_some()
-        {
-            latest = Some(entry);
-        }
+/// Returns the current turn's user and assistant messages, reduced to what the
+/// capture level allows.
+fn read_turn_messages(transcript_path: &str, options: &MapOptions) -> Vec<Message> {
+    if options.capture == CaptureMode::Off {
+        return Vec::new();
     }
-
-    latest
+    let expanded_path = expand_path(transcript_path);
+    crate::transcript::read(Path::new(&expanded_path), |t| {
+        t.turn
+            .iter()
+            .map(|m| {
+                let mut msg = m.message.clone();
+                if options.capture == CaptureMode::Content {
+                    msg.truncated |= truncate_chars(&mut msg.content, options.max_chars);
+                } else {
+                    msg.content.clear();
+                    msg.truncated = false;
+                }
+                msg
+            })
+            .collect()
+    })
+    .unwrap_or_default()
 }
 
 /// Enriches payload with data from the latest assistant message.
@@ -96,6 +150,7 @@ fn read_latest_assistant_message(transcript_path: &str) -> Option<Json> {
 /// Mutates `payload` in-place to add:
 /// - `model`: Model identifier
 /// - `usage`: Aggregated token counts (prompt_tokens, completion_tokens, total_tokens)
+///   plus the cache breakdown they were summed from
 /// - `finish_reason`: Mapped from `stop_reason`
 /// - `timestamp`: Event timestamp (only if not already present)
 ///
@@ -112,6 +167,10 @@ fn read_latest_assistant_message(transcript_path: &str) -> Option<Json> {
 /// completion_tokens = output_tokens
 /// total_tokens = prompt_tokens + completion_tokens
 /// ```
+///
+/// The three prompt components are kept alongside as `input_tokens`,
+/// `cache_creation_input_tokens` and `cache_read_input_tokens`, so cache
+/// effectiveness stays visible.
 fn enrich_from_transcript(payload: &mut Json, latest_msg: &Json) {
     let Some(msg) = latest_msg.get("message") else {
         return;
@@ -147,6 +206,9 @@ fn enrich_from_transcript(payload: &mut Json, latest_msg: &Json) {
             "prompt_tokens": input_tokens + cache_creation + cache_read,
             "completion_tokens": output_tokens,
             "total_tokens": input_tokens + cache_creation + cache_read + output_tokens,
+            "input_tokens": input_tokens,
+            "cache_creation_input_tokens": cache_creation,
+            "cache_read_input_tokens": cache_read,
             "token_counts_estimated": false
         });
 
@@ -186,19 +248,29 @@ fn enrich_from_transcript(payload: &mut Json, latest_msg: &Json) {
 /// Applies defaults for missing fields and preserves the original payload in
 /// `extensions["tap.raw"]` for audit purposes.
 ///
+/// With message capture enabled in `options`, `agent.stop` events also get the
+/// turn's messages in `inputs.messages_compact`.
+///
 /// # Errors
 ///
 /// Returns an error if fast path deserialization fails due to invalid TraceV1 structure.
-pub fn from_tap_frame(v: Json) -> Result<TraceV1> {
+pub fn from_tap_frame(v: Json, options: &MapOptions) -> Result<TraceV1> {
     // Fast path: Accept pre-formed TraceV1 events from newer plugins.
     if v.get("schema_version").is_some() && v.get("ids").is_some() {
         return serde_json::from_value::<TraceV1>(v).map_err(|e| anyhow!("TraceV1 parse: {e}"));
     }
 
     // Fallback path: Extract from legacy tap wrapper format.
+    // Hook scripts that don't pass --event still carry the name in the payload.
     let event = v
         .get("event")
         .and_then(|x| x.as_str())
+        .filter(|e| !e.is_empty() && *e != "unknown")
+        .or_else(|| {
+            v.get("payload")
+                .and_then(|p| p.get("hook_event_name"))
+                .and_then(|x| x.as_str())
+        })
         .unwrap_or("unknown")
         .to_string();
     let ts = v
@@ -261,7 +333,12 @@ pub fn from_tap_frame(v: Json) -> Result<TraceV1> {
     if let Some(pid) = env.get("pid") {
         t.context.pid = as_u32_sat(pid);
     }
-    if let Some(sid) = env.get("session_id").and_then(|x| x.as_str()) {
+    if let Some(sid) = env
+        .get("session_id")
+        .and_then(|x| x.as_str())
+        .filter(|s| !s.is_empty())
+        .or_else(|| payload.get("session_id").and_then(|x| x.as_str()))
+    {
         t.ids.session_id = sid.to_string();
     }
 
@@ -273,6 +350,15 @@ pub fn from_tap_frame(v: Json) -> Result<TraceV1> {
             .and_then(|id| id.as_str())
             .unwrap_or("")
             .to_string();
+        t.ids.message_id = t.ids.conversation_id.clone();
+    }
+    let mut model_latency = None;
+    if !t.ids.message_id.is_empty()
+        && let Some(path) = payload.get("transcript_path").and_then(|p| p.as_str())
+    {
+        let (tool_calls, latency) = read_message_details(path, &t.ids.message_id);
+        t.outputs.tool_calls = tool_calls;
+        model_latency = latency;
     }
 
     // Extract model configuration.
@@ -294,15 +380,9 @@ pub fn from_tap_frame(v: Json) -> Result<TraceV1> {
         .map(|v| v as f32)
         .unwrap_or(0.0);
 
-    t.configuration.top_k = payload
-        .get("top_k")
-        .map(|v| as_u32_sat(v))
-        .unwrap_or(0);
+    t.configuration.top_k = payload.get("top_k").map(as_u32_sat).unwrap_or(0);
 
-    t.configuration.max_tokens = payload
-        .get("max_tokens")
-        .map(|v| as_u32_sat(v))
-        .unwrap_or(0);
+    t.configuration.max_tokens = payload.get("max_tokens").map(as_u32_sat).unwrap_or(0);
 
     // Extract tool usage details.
     if let Some(name) = payload.get("tool_name").and_then(|x| x.as_str()) {
@@ -316,8 +396,16 @@ pub fn from_tap_frame(v: Json) -> Result<TraceV1> {
     }
 
     // Extract output metadata.
-    if let Some(resp) = payload.get("tool_response").and_then(|x| x.as_str()) {
-        t.outputs.assistant_text = resp.to_string();
+    match payload.get("tool_response") {
+        Some(Json::String(resp)) => t.outputs.assistant_text = resp.clone(),
+        // Bash reports `{stdout, stderr, ...}`; other tools' objects are kept as JSON.
+        Some(resp @ Json::Object(o)) => {
+            t.outputs.assistant_text = match o.get("stdout").and_then(|x| x.as_str()) {
+                Some(stdout) => stdout.to_string(),
+                None => resp.to_string(),
+            }
+        }
+        _ => {}
     }
     if let Some(fr) = payload.get("finish_reason").and_then(|x| x.as_str()) {
         t.outputs.finish_reason = fr.to_string();
@@ -338,6 +426,15 @@ pub fn from_tap_frame(v: Json) -> Result<TraceV1> {
         t.metrics.completion_tokens = completion_tokens;
         t.metrics.total_tokens = total_tokens;
         t.metrics.token_counts_estimated = tokens_estimated;
+        t.metrics.uncached_input_tokens = u.get("input_tokens").map(as_u32_sat).unwrap_or(0);
+        t.metrics.cache_creation_input_tokens = u
+            .get("cache_creation_input_tokens")
+            .map(as_u32_sat)
+            .unwrap_or(0);
+        t.metrics.cache_read_input_tokens = u
+            .get("cache_read_input_tokens")
+            .map(as_u32_sat)
+            .unwrap_or(0);
 
         // Also populate outputs object for Beak compatibility
         t.outputs.input_tokens = prompt_tokens;
@@ -365,6 +462,32 @@ pub fn from_tap_frame(v: Json) -> Result<TraceV1> {
         })
         .unwrap_or(false);
 
+    // Hooks report no model timing; take it from when the transcript entries were
+    // written. Tool and turn durations come from hook timing in `spans`.
+    if let Some((first_token, provider)) = model_latency
+        && payload.get("latency_ms").is_none()
+    {
+        t.metrics.latency_ms.first_token = first_token;
+        t.metrics.latency_ms.provider = provider;
+        t.metrics.latency_estimated = false;
+    }
+
+    // Keep the hook name as sent, so events without a canonical name stay distinguishable.
+    set_nonempty_label(&mut t, "hook_event", &event);
+    extract_event_fields(&mut t, &payload, latest_msg.as_ref(), options);
+    crate::context::apply(&mut t);
+    if options.git
+        && let Some(cwd) = payload.get("cwd").and_then(|x| x.as_str())
+    {
+        crate::git::annotate(&mut t, &expand_path(cwd));
+    }
+
+    if t.event == "agent.stop"
+        && let Some(path) = payload.get("transcript_path").and_then(|p| p.as_str())
+    {
+        t.inputs.messages_compact = read_turn_messages(path, options);
+    }
+
     // Preserve original tap payload in extensions for audit trail.
     let mut ext = serde_json::Map::new();
     ext.insert("tap.raw".to_string(), raw_payload_for_audit);
@@ -373,20 +496,477 @@ pub fn from_tap_frame(v: Json) -> Result<TraceV1> {
     Ok(t)
 }
 
+/// Sets a label like [`TraceV1::set_label`], skipping empty values.
+fn set_nonempty_label(t: &mut TraceV1, key: &str, value: &str) {
+    if !value.is_empty() {
+        t.set_label(key, value);
+    }
+}
+
+/// Extracts fields specific to each canonical event from the hook payload.
+///
+/// Small scalar metadata goes into labels. Prompt text is not copied; only its
+/// length is recorded.
+fn extract_event_fields(
+    t: &mut TraceV1,
+    payload: &Json,
+    latest_msg: Option<&Json>,
+    options: &MapOptions,
+) {
+    let str_field = |key: &str| payload.get(key).and_then(|x| x.as_str()).unwrap_or("");
+    let bool_field = |key: &str| payload.get(key).and_then(|x| x.as_bool());
+
+    match t.event.as_str() {
+        "tool.pre" | "tool.post" => {
+            set_nonempty_label(t, "tool_use_id", str_field("tool_use_id"));
+            if t.event == "tool.post" {
+                crate::outcome::classify(&t.inputs.tool.name, payload).apply(t);
+            }
+            extract_tool_fields(t, payload, options);
+            if let Some(file) = touched_file(&t.inputs.tool.name, &t.inputs.tool.args) {
+                t.inputs.files = vec![relative_to_cwd(file, str_field("cwd"))];
+            }
+        }
+        "prompt.submit" => {
+            if let Some(prompt) = payload.get("prompt").and_then(|x| x.as_str()) {
+                set_nonempty_label(t, "prompt_chars", &prompt.chars().count().to_string());
+            }
+        }
+        "agent.stop" | "subagent.stop" => {
+            if let Some(active) = bool_field("stop_hook_active") {
+                set_nonempty_label(t, "stop_hook_active", &active.to_string());
+            }
+            // The final response of the (sub)agent is the latest assistant message.
+            if t.outputs.assistant_text.is_empty()
+                && let Some(msg) = latest_msg
+            {
+                t.outputs.assistant_text = message_text(msg);
+            }
+        }
+        "notification" => {
+            set_nonempty_label(t, "notification_message", str_field("message"));
+            set_nonempty_label(t, "notification_type", str_field("notification_type"));
+        }
+        "compact.pre" => {
+            set_nonempty_label(t, "compact_trigger", str_field("trigger"));
+            let has_instructions = !str_field("custom_instructions").is_empty();
+            set_nonempty_label(
+                t,
+                "compact_custom_instructions",
+                &has_instructions.to_string(),
+            );
+        }
+        "session.start" => set_nonempty_label(t, "session_source", str_field("source")),
+        "session.end" => set_nonempty_label(t, "session_end_reason", str_field("reason")),
+        _ => {}
+    }
+
+    set_nonempty_label(t, "cwd", str_field("cwd"));
+    set_nonempty_label(t, "permission_mode", str_field("permission_mode"));
+    // Set on hooks fired by a subagent; spans.rs uses them to find its Task.
+    for key in ["agent_id", "agent_type", "parent_tool_use_id"] {
+        set_nonempty_label(t, key, str_field(key));
+    }
+}
+
+/// Pulls typed attributes of one tool from its `tool_input` and `tool_response`
+/// (`Null` on `tool.pre`) into labels.
+type ToolExtractor = fn(&mut TraceV1, &Json, &Json, &MapOptions);
+
+/// Per-tool extractors. Tools not listed keep only the generic fields.
+const TOOL_EXTRACTORS: &[(&str, ToolExtractor)] = &[
+    ("Bash", extract_bash),
+    ("Read", extract_read),
+    ("Edit", extract_edit),
+    ("MultiEdit", extract_edit),
+    ("Write", extract_write),
+    ("Grep", extract_grep),
+    ("Glob", extract_glob),
+    ("WebFetch", extract_web_fetch),
+    ("Task", extract_task),
+    ("Agent", extract_task),
+];
+
+/// Characters of a search pattern kept in a label.
+const MAX_LABEL_CHARS: usize = 256;
+
+fn extract_tool_fields(t: &mut TraceV1, payload: &Json, options: &MapOptions) {
+    if let Some((server, tool)) = parse_mcp_tool(&t.inputs.tool.name) {
+        let (server, tool) = (server.to_string(), tool.to_string());
+        set_nonempty_label(t, "mcp_server", &server);
+        set_nonempty_label(t, "mcp_tool", &tool);
+        return;
+    }
+    let Some((_, extract)) = TOOL_EXTRACTORS
+        .iter()
+        .find(|(name, _)| *name == t.inputs.tool.name)
+    else {
+        return;
+    };
+    let input = payload.get("tool_input").unwrap_or(&Json::Null);
+    let response = payload.get("tool_response").unwrap_or(&Json::Null);
+    extract(t, input, response, options);
+}
+
+/// Splits an MCP tool name, `mcp__<server>__<tool>`, into server and tool.
+///
+/// Server names can't contain `__` (Claude Code replaces other characters with a
+/// single `_`), so the first `__` after the prefix ends the server.
+fn parse_mcp_tool(name: &str) -> Option<(&str, &str)> {
+    let (server, tool) = name.strip_prefix("mcp__")?.split_once("__")?;
+    (!server.is_empty() && !tool.is_empty()).then_some((server, tool))
+}
+
+/// Path of the file a file tool reads or modifies, from its input.
+fn touched_file<'a>(tool: &str, input: &'a Json) -> Option<&'a str> {
+    let key = match tool {
+        "Read" | "Edit" | "MultiEdit" | "Write" => "file_path",
+        "NotebookEdit" => "notebook_path",
+        _ => return None,
+    };
+    input
+        .get(key)
+        .and_then(|x| x.as_str())
+        .filter(|p| !p.is_empty())
+}
+
+/// Makes `path` relative to `cwd` when it lies inside it; other paths are kept
+/// as given, minus a leading `./`.
+fn relative_to_cwd(path: &str, cwd: &str) -> String {
+    if !cwd.is_empty()
+        && let Ok(rel) = Path::new(path).strip_prefix(cwd)
+    {
+        let rel = rel.to_string_lossy();
+        return if rel.is_empty() {
+            ".".to_string()
+        } else {
+            rel.into_owned()
+        };
+    }
+    path.strip_prefix("./").unwrap_or(path).to_string()
+}
+
+fn str_at<'a>(v: &'a Json, pointer: &str) -> &'a str {
+    v.pointer(pointer).and_then(|x| x.as_str()).unwrap_or("")
+}
+
+fn set_count_label(t: &mut TraceV1, key: &str, n: Option<u64>) {
+    if let Some(n) = n {
+        set_nonempty_label(t, key, &n.to_string());
+    }
+}
+
+fn set_clipped_label(t: &mut TraceV1, key: &str, value: &str) {
+    let mut value = value.to_string();
+    truncate_chars(&mut value, MAX_LABEL_CHARS);
+    set_nonempty_label(t, key, &value);
+}
+
+fn line_count(s: &str) -> u64 {
+    s.lines().count() as u64
+}
+
+fn extract_bash(t: &mut TraceV1, input: &Json, response: &Json, options: &MapOptions) {
+    let command = str_at(input, "/command");
+    // Command lines can carry secrets, so the text is kept only with content capture.
+    if options.capture == CaptureMode::Content {
+        let mut text = command.to_string();
+        truncate_chars(&mut text, options.max_chars);
+        set_nonempty_label(t, "tool_command", &text);
+    }
+    let chars = command.chars().count() as u64;
+    set_count_label(t, "tool_command_chars", (chars > 0).then_some(chars));
+    // First word that isn't an environment assignment, without its directory.
+    let program = command
+        .split_whitespace()
+        .find(|w| !w.contains('='))
+        .map(|w| w.rsplit('/').next().unwrap_or(w));
+    set_nonempty_label(t, "tool_command_name", program.unwrap_or(""));
+    if let Some(bg) = input.get("run_in_background").and_then(|x| x.as_bool()) {
+        set_nonempty_label(t, "tool_background", &bg.to_string());
+    }
+    let stdout = response.get("stdout").and_then(|x| x.as_str());
+    set_count_label(t, "tool_output_bytes", stdout.map(|s| s.len() as u64));
+}
+
+fn extract_read(t: &mut TraceV1, input: &Json, response: &Json, _: &MapOptions) {
+    set_nonempty_label(t, "tool_file_path", str_at(input, "/file_path"));
+    set_count_label(
+        t,
+        "tool_offset",
+        input.get("offset").and_then(|x| x.as_u64()),
+    );
+    set_count_label(t, "tool_limit", input.get("limit").and_then(|x| x.as_u64()));
+    let file = response.get("file");
+    let lines = file
+        .and_then(|f| f.get("numLines"))
+        .and_then(|x| x.as_u64());
+    let content = file.and_then(|f| f.get("content")).and_then(|x| x.as_str());
+    set_count_label(t, "tool_lines", lines.or(content.map(line_count)));
+    set_count_label(t, "tool_bytes", content.map(|c| c.len() as u64));
+    set_count_label(
+        t,
+        "tool_total_lines",
+        file.and_then(|f| f.get("totalLines"))
+            .and_then(|x| x.as_u64()),
+    );
+}
+
+fn extract_edit(t: &mut TraceV1, input: &Json, response: &Json, _: &MapOptions) {
+    set_nonempty_label(t, "tool_file_path", str_at(input, "/file_path"));
+    if let Some(all) = input.get("replace_all").and_then(|x| x.as_bool()) {
+        set_nonempty_label(t, "tool_replace_all", &all.to_string());
+    }
+    let edits = input.get("edits").and_then(|x| x.as_array());
+    set_count_label(t, "tool_edits", edits.map(|e| e.len() as u64));
+
+    // MultiEdit lists its replacements; Edit is a single one.
+    let edits = edits
+        .map(|e| e.as_slice())
+        .unwrap_or(std::slice::from_ref(input));
+    let replace_all =
+        |edit: &Json| edit.get("replace_all").and_then(|x| x.as_bool()) == Some(true);
+    let stats = if let Some(stats) = DiffStats::from_patch(response) {
+        stats
+    } else if let Some(original) = response.get("originalFile").and_then(|x| x.as_str()) {
+        // Replay the edits on the file as it was, so every replacement is counted.
+        let mut edited = original.to_string();
+        for edit in edits {
+            let (old, new) = (str_at(edit, "/old_string"), str_at(edit, "/new_string"));
+            if !old.is_empty() {
+                let count = if replace_all(edit) { usize::MAX } else { 1 };
+                edited = edited.replacen(old, new, count);
+            }
+        }
+        DiffStats::between(original, &edited)
+    } else if edits.iter().any(replace_all) {
+        // The number of occurrences replaced is unknown; no stats beat an undercount.
+        return;
+    } else {
+        let mut stats = DiffStats::default();
+        for edit in edits {
+            stats += DiffStats::between(str_at(edit, "/old_string"), str_at(edit, "/new_string"));
+        }
+        stats
+    };
+    stats.apply(t);
+}
+
+fn extract_write(t: &mut TraceV1, input: &Json, response: &Json, _: &MapOptions) {
+    set_nonempty_label(t, "tool_file_path", str_at(input, "/file_path"));
+    let content = input.get("content").and_then(|x| x.as_str());
+    set_count_label(t, "tool_lines", content.map(line_count));
+    set_count_label(t, "tool_bytes", content.map(|c| c.len() as u64));
+    // `create` for a new file, `update` when it replaced one.
+    set_nonempty_label(t, "tool_write_type", str_at(response, "/type"));
+
+    // Overwrites report a patch against the old file; otherwise all lines are new.
+    let stats = DiffStats::from_patch(response)
+        .unwrap_or_else(|| DiffStats::between("", content.unwrap_or("")));
+    stats.apply(t);
+}
+
+/// Lines and bytes added and removed by a code-changing tool call.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct DiffStats {
+    lines_added: u32,
+    lines_removed: u32,
+    bytes_added: u32,
+    bytes_removed: u32,
+}
+
+/// Line pairs compared by [`DiffStats::between`]; beyond this every differing
+/// line counts as replaced.
+const MAX_DIFF_CELLS: usize = 1_000_000;
+
+impl DiffStats {
+    /// Counts the `+` and `-` lines of Claude Code's `structuredPatch`.
+    fn from_patch(response: &Json) -> Option<Self> {
+        let hunks = response.get("structuredPatch")?.as_array()?;
+        let mut stats = Self::default();
+        for line in hunks
+            .iter()
+            .filter_map(|h| h.get("lines").and_then(|l| l.as_array()))
+            .flatten()
+            .filter_map(|l| l.as_str())
+        {
+            let bytes = sat_u32(line.len()); // the `+`/`-` stands in for the newline
+            if line.starts_with('+') {
+                stats.lines_added += 1;
+                stats.bytes_added = stats.bytes_added.saturating_add(bytes);
+            } else if line.starts_with('-') {
+                stats.lines_removed += 1;
+                stats.bytes_removed = stats.bytes_removed.saturating_add(bytes);
+            }
+        }
+        Some(stats)
+    }
+
+    /// Line diff of `old` against `new`, by longest common subsequence.
+    fn between(old: &str, new: &str) -> Self {
+        let old: Vec<&str> = old.lines().collect();
+        let new: Vec<&str> = new.lines().collect();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let (old, new) = (&old[prefix..], &new[prefix..]);
+        let suffix = old
+            .iter()
+            .rev()
+            .zip(new.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+
+        let (removed, added) = changed_lines(old, new);
+        let bytes = |lines: &[&str]| sat_u32(lines.iter().map(|l| l.len() + 1).sum());
+        Self {
+            lines_added: sat_u32(added.len()),
+            lines_removed: sat_u32(removed.len()),
+            bytes_added: bytes(&added),
+            bytes_removed: bytes(&removed),
+        }
+    }
+
+    /// Sets the span's diff metrics.
+    fn apply(self, t: &mut TraceV1) {
+        t.metrics.lines_added = self.lines_added;
+        t.metrics.lines_removed = self.lines_removed;
+        t.metrics.bytes_added = self.bytes_added;
+        t.metrics.bytes_removed = self.bytes_removed;
+    }
+}
+
+impl std::ops::AddAssign for DiffStats {
+    fn add_assign(&mut self, other: Self) {
+        self.lines_added = self.lines_added.saturating_add(other.lines_added);
+        self.lines_removed = self.lines_removed.saturating_add(other.lines_removed);
+        self.bytes_added = self.bytes_added.saturating_add(other.bytes_added);
+        self.bytes_removed = self.bytes_removed.saturating_add(other.bytes_removed);
+    }
+}
+
+/// Lines of `old` not kept in `new` and lines of `new` not in `old`, by longest
+/// common subsequence.
+fn changed_lines<'a>(old: &[&'a str], new: &[&'a str]) -> (Vec<&'a str>, Vec<&'a str>) {
+    if old.len().saturating_mul(new.len()) > MAX_DIFF_CELLS {
+        return (old.to_vec(), new.to_vec());
+    }
+    // lcs[i][j]: common subsequence length of old[i..] and new[j..]. With the
+    // cell cap the shorter side has at most 1000 lines, so u16 is enough.
+    let width = new.len() + 1;
+    let mut lcs = vec![0u16; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            removed.push(old[i]);
+            i += 1;
+        } else {
+            added.push(new[j]);
+            j += 1;
+        }
+    }
+    removed.extend_from_slice(&old[i..]);
+    added.extend_from_slice(&new[j..]);
+    (removed, added)
+}
+
+fn sat_u32(n: usize) -> u32 {
+    n.min(u32::MAX as usize) as u32
+}
+
+fn extract_grep(t: &mut TraceV1, input: &Json, response: &Json, _: &MapOptions) {
+    set_clipped_label(t, "tool_pattern", str_at(input, "/pattern"));
+    set_nonempty_label(t, "tool_path", str_at(input, "/path"));
+    set_nonempty_label(t, "tool_glob", str_at(input, "/glob"));
+    set_nonempty_label(t, "tool_output_mode", str_at(input, "/output_mode"));
+    set_count_label(
+        t,
+        "tool_match_files",
+        response.get("numFiles").and_then(|x| x.as_u64()),
+    );
+    set_count_label(
+        t,
+        "tool_match_lines",
+        response.get("numLines").and_then(|x| x.as_u64()),
+    );
+}
+
+fn extract_glob(t: &mut TraceV1, input: &Json, response: &Json, _: &MapOptions) {
+    set_clipped_label(t, "tool_pattern", str_at(input, "/pattern"));
+    set_nonempty_label(t, "tool_path", str_at(input, "/path"));
+    set_count_label(
+        t,
+        "tool_match_files",
+        response.get("numFiles").and_then(|x| x.as_u64()),
+    );
+}
+
+fn extract_web_fetch(t: &mut TraceV1, input: &Json, response: &Json, _: &MapOptions) {
+    let host = reqwest::Url::parse(str_at(input, "/url"))
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string));
+    set_nonempty_label(t, "tool_url_host", host.as_deref().unwrap_or(""));
+    set_count_label(
+        t,
+        "tool_http_status",
+        response.get("code").and_then(|x| x.as_u64()),
+    );
+    set_count_label(
+        t,
+        "tool_bytes",
+        response.get("bytes").and_then(|x| x.as_u64()),
+    );
+}
+
+fn extract_task(t: &mut TraceV1, input: &Json, response: &Json, _: &MapOptions) {
+    set_nonempty_label(t, "subagent_type", str_at(input, "/subagent_type"));
+    // The Task result reports the subagent's own totals.
+    for (field, key) in [
+        ("totalTokens", "subagent_total_tokens"),
+        ("totalToolUseCount", "subagent_tool_calls"),
+    ] {
+        set_count_label(t, key, response.get(field).and_then(|x| x.as_u64()));
+    }
+}
+
 /// Normalizes event type strings to canonical form.
 ///
-/// Maps PascalCase (e.g., "PostToolUse") and dotted notation (e.g., "tool.post")
-/// to a single canonical format.
+/// Maps Claude Code hook names (e.g., "PostToolUse") and dotted notation
+/// (e.g., "tool.post") to a single canonical format.
 ///
 /// Supported mappings:
-/// - `PostToolUse` / `tool.post` → `"tool.post"`
+/// - `PreToolUse` / `tool.pre` → `"tool.pre"`
+/// - `PostToolUse` / `PostToolUseFailure` / `tool.post` → `"tool.post"`
+/// - `UserPromptSubmit` / `prompt.submit` → `"prompt.submit"`
+/// - `Stop` / `agent.stop` → `"agent.stop"`
+/// - `SubagentStop` / `subagent.stop` → `"subagent.stop"`
+/// - `Notification` / `notification` → `"notification"`
+/// - `PreCompact` / `compact.pre` → `"compact.pre"`
 /// - `ModelEnd` / `model.end` → `"model.end"`
 /// - `SessionStart` / `session.start` → `"session.start"`
 /// - `SessionEnd` / `session.end` → `"session.end"`
-/// - Unknown → `"unknown"`
+/// - Unknown → `"unknown"` (the raw name is kept in the `hook_event` label)
 fn normalize_event(e: &str) -> &str {
     match e {
-        "PostToolUse" | "tool.post" => "tool.post",
+        "PreToolUse" | "tool.pre" => "tool.pre",
+        "PostToolUse" | "PostToolUseFailure" | "tool.post" => "tool.post",
+        "UserPromptSubmit" | "prompt.submit" => "prompt.submit",
+        "Stop" | "agent.stop" => "agent.stop",
+        "SubagentStop" | "subagent.stop" => "subagent.stop",
+        "Notification" | "notification" => "notification",
+        "PreCompact" | "compact.pre" => "compact.pre",
         "ModelEnd" | "model.end" => "model.end",
         "SessionStart" | "session.start" => "session.start",
         "SessionEnd" | "session.end" => "session.end",
@@ -604,7 +1184,7 @@ mod tests {
             "version": "1.0.0"
         });
 
-        let result = from_tap_frame(frame);
+        let result = from_tap_frame(frame, &MapOptions::default());
         assert!(result.is_ok(), "from_tap_frame should succeed");
 
         let trace = result.unwrap();
@@ -612,6 +1192,9 @@ mod tests {
         assert_eq!(trace.metrics.prompt_tokens, 3500); // 1000+500+2000
         assert_eq!(trace.metrics.completion_tokens, 150);
         assert_eq!(trace.metrics.total_tokens, 3650);
+        assert_eq!(trace.metrics.uncached_input_tokens, 1000);
+        assert_eq!(trace.metrics.cache_creation_input_tokens, 500);
+        assert_eq!(trace.metrics.cache_read_input_tokens, 2000);
         assert_eq!(trace.outputs.finish_reason, "tool_use");
     }
 
@@ -645,7 +1228,7 @@ mod tests {
             "version": "0.1.0"
         });
 
-        let result = from_tap_frame(frame);
+        let result = from_tap_frame(frame, &MapOptions::default());
         assert!(result.is_ok(), "from_tap_frame should succeed");
 
         let trace = result.unwrap();
@@ -654,16 +1237,668 @@ mod tests {
         assert_eq!(trace.metrics.prompt_tokens, 3500); // 1000+500+2000
         assert_eq!(trace.metrics.completion_tokens, 150);
         assert_eq!(trace.metrics.total_tokens, 3650);
-        assert_eq!(trace.metrics.token_counts_estimated, false);
+        assert!(!trace.metrics.token_counts_estimated);
 
         // Verify tokens are ALSO in outputs object (new behavior for Beak compatibility)
         assert_eq!(trace.outputs.input_tokens, 3500);
         assert_eq!(trace.outputs.output_tokens, 150);
         assert_eq!(trace.outputs.total_tokens, 3650);
-        assert_eq!(trace.outputs.tokens_estimated, false);
+        assert!(!trace.outputs.tokens_estimated);
 
         // Verify conversation_id is extracted from message.id
         assert_eq!(trace.ids.conversation_id, "msg_abc123");
+        assert_eq!(trace.ids.message_id, "msg_abc123");
+    }
+
+    fn label<'a>(t: &'a TraceV1, key: &str) -> Option<&'a str> {
+        t.labels
+            .iter()
+            .find(|l| l.key == key)
+            .map(|l| l.value.as_str())
+    }
+
+    fn hook_frame(event: &str, payload: Json) -> Json {
+        serde_json::json!({
+            "event": event,
+            "ts": "2025-11-13T10:30:00Z",
+            "env": { "session_id": "sess-1" },
+            "payload": payload
+        })
+    }
+
+    #[test]
+    fn test_normalize_event_covers_all_hooks() {
+        let cases = [
+            ("PreToolUse", "tool.pre"),
+            ("PostToolUse", "tool.post"),
+            ("PostToolUseFailure", "tool.post"),
+            ("UserPromptSubmit", "prompt.submit"),
+            ("Stop", "agent.stop"),
+            ("SubagentStop", "subagent.stop"),
+            ("Notification", "notification"),
+            ("PreCompact", "compact.pre"),
+            ("SessionStart", "session.start"),
+            ("SessionEnd", "session.end"),
+        ];
+        for (hook, canonical) in cases {
+            assert_eq!(normalize_event(hook), canonical, "{hook}");
+            assert_eq!(normalize_event(canonical), canonical, "{canonical}");
+        }
+        assert_eq!(normalize_event("SomethingNew"), "unknown");
+    }
+
+    #[test]
+    fn test_unknown_event_keeps_raw_hook_name() {
+        let trace = from_tap_frame(
+            hook_frame("SomethingNew", serde_json::json!({})),
+            &MapOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(trace.event, "unknown");
+        assert_eq!(label(&trace, "hook_event"), Some("SomethingNew"));
+    }
+
+    #[test]
+    fn test_event_name_falls_back_to_payload() {
+        let mut frame = hook_frame(
+            "unknown",
+            serde_json::json!({ "hook_event_name": "PreToolUse", "session_id": "from-payload" }),
+        );
+        frame["env"] = serde_json::json!({});
+
+        let trace = from_tap_frame(frame, &MapOptions::default()).unwrap();
+        assert_eq!(trace.event, "tool.pre");
+        assert_eq!(trace.ids.session_id, "from-payload");
+    }
+
+    #[test]
+    fn test_pre_tool_use_extracts_tool_fields() {
+        let trace = from_tap_frame(
+            hook_frame(
+                "PreToolUse",
+                serde_json::json!({
+                    "tool_name": "Bash",
+                    "tool_input": { "command": "ls" },
+                    "tool_use_id": "toolu_01",
+                    "cwd": "/work",
+                    "permission_mode": "default"
+                }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(trace.event, "tool.pre");
+        assert_eq!(trace.inputs.tool.name, "Bash");
+        assert_eq!(trace.inputs.tool.args["command"], "ls");
+        assert_eq!(label(&trace, "tool_use_id"), Some("toolu_01"));
+        assert_eq!(label(&trace, "cwd"), Some("/work"));
+        assert_eq!(label(&trace, "permission_mode"), Some("default"));
+    }
+
+    #[test]
+    fn test_bash_command_text_needs_content_capture() {
+        let frame = || {
+            hook_frame(
+                "PreToolUse",
+                serde_json::json!({
+                    "tool_name": "Bash",
+                    "tool_input": { "command": "curl -H 'Authorization: Bearer s3cret' example.com" }
+                }),
+            )
+        };
+        let options = |capture| MapOptions {
+            capture,
+            max_chars: 12,
+            ..Default::default()
+        };
+
+        let metadata = from_tap_frame(frame(), &options(CaptureMode::Metadata)).unwrap();
+        assert_eq!(label(&metadata, "tool_command"), None);
+        assert_eq!(label(&metadata, "tool_command_name"), Some("curl"));
+        assert_eq!(label(&metadata, "tool_command_chars"), Some("50"));
+
+        let content = from_tap_frame(frame(), &options(CaptureMode::Content)).unwrap();
+        assert_eq!(label(&content, "tool_command"), Some("curl -H 'Aut"));
+    }
+
+    #[test]
+    fn test_tool_extractors_label_typed_fields() {
+        let post = |tool: &str, input: Json, response: Json| {
+            from_tap_frame(
+                hook_frame(
+                    "PostToolUse",
+                    serde_json::json!({
+                        "tool_name": tool,
+                        "tool_input": input,
+                        "tool_response": response
+                    }),
+                ),
+                &MapOptions::default(),
+            )
+            .unwrap()
+        };
+
+        let bash = post(
+            "Bash",
+            serde_json::json!({ "command": "RUST_LOG=1 /usr/bin/cargo test" }),
+            serde_json::json!({ "stdout": "ok\n", "stderr": "" }),
+        );
+        assert_eq!(
+            label(&bash, "tool_command"),
+            None,
+            "text needs content capture"
+        );
+        assert_eq!(label(&bash, "tool_command_chars"), Some("30"));
+        assert_eq!(label(&bash, "tool_command_name"), Some("cargo"));
+        assert_eq!(label(&bash, "tool_output_bytes"), Some("3"));
+
+        let read = post(
+            "Read",
+            serde_json::json!({ "file_path": "/src/lib.rs", "offset": 10 }),
+            serde_json::json!({ "type": "text", "file": { "content": "a\nb", "numLines": 2, "totalLines": 40 } }),
+        );
+        assert_eq!(label(&read, "tool_file_path"), Some("/src/lib.rs"));
+        assert_eq!(label(&read, "tool_offset"), Some("10"));
+        assert_eq!(label(&read, "tool_lines"), Some("2"));
+        assert_eq!(label(&read, "tool_bytes"), Some("3"));
+        assert_eq!(label(&read, "tool_total_lines"), Some("40"));
+
+        let write = post(
+            "Write",
+            serde_json::json!({ "file_path": "/a.md", "content": "x\ny\nz" }),
+            serde_json::json!({ "type": "create" }),
+        );
+        assert_eq!(label(&write, "tool_lines"), Some("3"));
+        assert_eq!(label(&write, "tool_write_type"), Some("create"));
+
+        let grep = post(
+            "Grep",
+            serde_json::json!({ "pattern": "fn main", "glob": "*.rs" }),
+            serde_json::json!({ "numFiles": 4 }),
+        );
+        assert_eq!(label(&grep, "tool_pattern"), Some("fn main"));
+        assert_eq!(label(&grep, "tool_glob"), Some("*.rs"));
+        assert_eq!(label(&grep, "tool_match_files"), Some("4"));
+
+        let fetch = post(
+            "WebFetch",
+            serde_json::json!({ "url": "https://docs.rs/serde/latest", "prompt": "?" }),
+            serde_json::json!({ "code": 200, "bytes": 5120 }),
+        );
+        assert_eq!(label(&fetch, "tool_url_host"), Some("docs.rs"));
+        assert_eq!(label(&fetch, "tool_http_status"), Some("200"));
+        assert_eq!(label(&fetch, "tool_bytes"), Some("5120"));
+
+        let other = post(
+            "NotebookEdit",
+            serde_json::json!({ "notebook_path": "/n.ipynb" }),
+            Json::Null,
+        );
+        assert_eq!(other.inputs.tool.args["notebook_path"], "/n.ipynb");
+        assert!(
+            other
+                .labels
+                .iter()
+                .all(|l| !l.key.starts_with("tool_") || l.key == "tool_status")
+        );
+    }
+
+    #[test]
+    fn test_mcp_tool_names_are_split() {
+        assert_eq!(
+            parse_mcp_tool("mcp__github__create_pull_request"),
+            Some(("github", "create_pull_request"))
+        );
+        assert_eq!(
+            parse_mcp_tool("mcp__plugin_docs_search__query__v2"),
+            Some(("plugin_docs_search", "query__v2"))
+        );
+        assert_eq!(parse_mcp_tool("mcp__github"), None);
+        assert_eq!(parse_mcp_tool("mcp____tool"), None);
+        assert_eq!(parse_mcp_tool("Bash"), None);
+
+        let trace = from_tap_frame(
+            hook_frame(
+                "PreToolUse",
+                serde_json::json!({ "tool_name": "mcp__sentry__search_issues", "tool_input": {} }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(trace.inputs.tool.name, "mcp__sentry__search_issues");
+        assert_eq!(label(&trace, "mcp_server"), Some("sentry"));
+        assert_eq!(label(&trace, "mcp_tool"), Some("search_issues"));
+    }
+
+    #[test]
+    fn test_file_tools_record_touched_files() {
+        let file_of = |tool: &str, input: Json| {
+            from_tap_frame(
+                hook_frame(
+                    "PostToolUse",
+                    serde_json::json!({ "tool_name": tool, "tool_input": input, "cwd": "/work/repo" }),
+                ),
+                &MapOptions::default(),
+            )
+            .unwrap()
+            .inputs
+            .files
+        };
+
+        assert_eq!(
+            file_of(
+                "Edit",
+                serde_json::json!({ "file_path": "/work/repo/src/lib.rs" })
+            ),
+            ["src/lib.rs"]
+        );
+        assert_eq!(
+            file_of("Read", serde_json::json!({ "file_path": "/etc/hosts" })),
+            ["/etc/hosts"]
+        );
+        assert_eq!(
+            file_of(
+                "NotebookEdit",
+                serde_json::json!({ "notebook_path": "./nb.ipynb" })
+            ),
+            ["nb.ipynb"]
+        );
+        assert_eq!(
+            file_of(
+                "Write",
+                serde_json::json!({ "file_path": "/work/repository/x" })
+            ),
+            ["/work/repository/x"],
+            "prefix must end at a path component"
+        );
+        assert!(file_of("Bash", serde_json::json!({ "command": "cat a.txt" })).is_empty());
+    }
+
+    #[test]
+    fn test_diff_stats_between_strings() {
+        let stats = DiffStats::between("a\nb\nc\nd", "a\nB\nc\nd\ne");
+        assert_eq!(
+            stats,
+            DiffStats {
+                lines_added: 2,
+                lines_removed: 1,
+                bytes_added: 4,
+                bytes_removed: 2,
+            }
+        );
+        assert_eq!(DiffStats::between("same\n", "same\n"), DiffStats::default());
+        assert_eq!(DiffStats::between("", "x\ny\n").lines_added, 2);
     }
 
+    #[test]
+    fn test_code_changing_tools_record_diff_stats() {
+        let post = |tool: &str, input: Json, response: Json| {
+            from_tap_frame(
+                hook_frame(
+                    "PostToolUse",
+                    serde_json::json!({ "tool_name": tool, "tool_input": input, "tool_response": response }),
+                ),
+                &MapOptions::default(),
+            )
+            .unwrap()
+            .metrics
+        };
+
+        let edit = post(
+            "Edit",
+            serde_json::json!({ "file_path": "/a.rs", "old_string": "let x = 1;", "new_string": "let x = 2;\nlet y = 3;" }),
+            Json::Null,
+        );
+        assert_eq!((edit.lines_added, edit.lines_removed), (2, 1));
+
+        let multi = post(
+            "MultiEdit",
+            serde_json::json!({
+                "file_path": "/a.rs",
+                "edits": [
+                    { "old_string": "a", "new_string": "b" },
+                    { "old_string": "c\nd", "new_string": "" }
+                ]
+            }),
+            Json::Null,
+        );
+        assert_eq!((multi.lines_added, multi.lines_removed), (1, 3));
+
+        // replace_all changes every occurrence: count them in the original file,
+        // or leave the stats out when it isn't in the response.
+        let replace_all = serde_json::json!({
+            "file_path": "/a.rs",
+            "old_string": "foo()",
+            "new_string": "bar()",
+            "replace_all": true
+        });
+        let replayed = post(
+            "Edit",
+            replace_all.clone(),
+            serde_json::json!({ "originalFile": "foo();\nkeep();\nfoo();\nfoo();\n" }),
+        );
+        assert_eq!((replayed.lines_added, replayed.lines_removed), (3, 3));
+        assert_eq!((replayed.bytes_added, replayed.bytes_removed), (21, 21));
+        let unknown = post("Edit", replace_all, Json::Null);
+        assert_eq!((unknown.lines_added, unknown.lines_removed), (0, 0));
+
+        let create = post(
+            "Write",
+            serde_json::json!({ "file_path": "/n.md", "content": "# Title\n\nBody\n" }),
+            serde_json::json!({ "type": "create" }),
+        );
+        assert_eq!((create.lines_added, create.lines_removed), (3, 0));
+        assert_eq!(create.bytes_added, 14);
+
+        // A patch in the response wins, e.g. for replace_all or overwrites.
+        let patched = post(
+            "Write",
+            serde_json::json!({ "file_path": "/n.md", "content": "new\n" }),
+            serde_json::json!({
+                "type": "update",
+                "structuredPatch": [{ "lines": [" keep", "-old one", "-old two", "+new"] }]
+            }),
+        );
+        assert_eq!((patched.lines_added, patched.lines_removed), (1, 2));
+        assert_eq!((patched.bytes_added, patched.bytes_removed), (4, 16));
+    }
+
+    #[test]
+    fn test_model_latency_from_transcript_timestamps() {
+        use std::io::Write;
+        use tempfile::NamedTempFile;
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"{{"type":"user","timestamp":"2025-11-13T10:00:00.000Z","message":{{"role":"user","content":"go"}}}}"#
+        )
+        .unwrap();
+        writeln!(
+            file,
+            r#"{{"type":"assistant","timestamp":"2025-11-13T10:00:02.500Z","message":{{"id":"msg_1","content":[{{"type":"text","text":"ok"}}],"usage":{{"output_tokens":1}}}}}}"#
+        )
+        .unwrap();
+        file.flush().unwrap();
+
+        let trace = from_tap_frame(
+            hook_frame(
+                "Stop",
+                serde_json::json!({ "transcript_path": file.path().to_str().unwrap() }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(trace.metrics.latency_ms.first_token, 2500);
+        assert_eq!(trace.metrics.latency_ms.provider, 2500);
+        assert_eq!(trace.metrics.latency_ms.total, 0, "left to hook timing");
+        assert!(!trace.metrics.latency_estimated);
+    }
+
+    #[test]
+    fn test_post_tool_use_records_status() {
+        let ok = from_tap_frame(
+            hook_frame(
+                "PostToolUse",
+                serde_json::json!({
+                    "tool_name": "Bash",
+                    "tool_response": { "stdout": "a.txt", "stderr": "", "interrupted": false }
+                }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(ok.outputs.assistant_text, "a.txt");
+        assert_eq!(label(&ok, "tool_status"), Some("success"));
+        assert_eq!(label(&ok, "tool_error_class"), None);
+
+        let failed = from_tap_frame(
+            hook_frame(
+                "PostToolUseFailure",
+                serde_json::json!({
+                    "tool_name": "Bash",
+                    "tool_use_id": "toolu_02",
+                    "error": "Exit code 1\nboom"
+                }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(failed.event, "tool.post");
+        assert_eq!(label(&failed, "tool_status"), Some("error"));
+        assert_eq!(label(&failed, "tool_error_class"), Some("exit_code"));
+        assert_eq!(label(&failed, "tool_exit_code"), Some("1"));
+    }
+
+    #[test]
+    fn test_prompt_submit_records_length_not_text() {
+        let trace = from_tap_frame(
+            hook_frame(
+                "UserPromptSubmit",
+                serde_json::json!({ "prompt": "fix the bug" }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(trace.event, "prompt.submit");
+        assert_eq!(label(&trace, "prompt_chars"), Some("11"));
+        assert!(!trace.labels.iter().any(|l| l.value.contains("fix the bug")));
+    }
+
+    #[test]
+    fn test_stop_takes_final_text_from_transcript() {
+        use std::io::Write;
+        use tempfile::NamedTempFile;
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"{{"type":"assistant","message":{{"id":"msg_1","model":"m","content":[{{"type":"text","text":"All done."}},{{"type":"tool_use","name":"Bash"}}],"usage":{{"input_tokens":10,"output_tokens":5}},"stop_reason":"end_turn"}}}}"#
+        )
+        .unwrap();
+        file.flush().unwrap();
+
+        for (hook, canonical) in [("Stop", "agent.stop"), ("SubagentStop", "subagent.stop")] {
+            let trace = from_tap_frame(
+                hook_frame(
+                    hook,
+                    serde_json::json!({
+                        "transcript_path": file.path().to_str().unwrap(),
+                        "stop_hook_active": false
+                    }),
+                ),
+                &MapOptions::default(),
+            )
+            .unwrap();
+
+            assert_eq!(trace.event, canonical);
+            assert_eq!(trace.outputs.assistant_text, "All done.");
+            assert_eq!(trace.outputs.finish_reason, "end_turn");
+            assert_eq!(label(&trace, "stop_hook_active"), Some("false"));
+        }
+    }
+
+    #[test]
+    fn test_task_tool_labels_subagent() {
+        let trace = from_tap_frame(
+            hook_frame(
+                "PostToolUse",
+                serde_json::json!({
+                    "tool_name": "Task",
+                    "tool_use_id": "toolu_task",
+                    "tool_input": { "subagent_type": "Explore", "prompt": "find it" },
+                    "tool_response": { "totalTokens": 5120, "totalToolUseCount": 3 }
+                }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(label(&trace, "subagent_type"), Some("Explore"));
+        assert_eq!(label(&trace, "subagent_total_tokens"), Some("5120"));
+        assert_eq!(label(&trace, "subagent_tool_calls"), Some("3"));
+    }
+
+    #[test]
+    fn test_labels_are_set_once_per_key() {
+        let mut t = TraceV1::default();
+        set_nonempty_label(&mut t, "k", "a");
+        set_nonempty_label(&mut t, "k", "b");
+        set_nonempty_label(&mut t, "k", "");
+        assert_eq!(t.labels.len(), 1);
+        assert_eq!(label(&t, "k"), Some("b"));
+    }
+
+    #[test]
+    fn test_subagent_hooks_keep_their_task_link() {
+        let trace = from_tap_frame(
+            hook_frame(
+                "PreToolUse",
+                serde_json::json!({
+                    "tool_name": "Grep",
+                    "tool_use_id": "toolu_grep",
+                    "agent_id": "a1",
+                    "agent_type": "Explore",
+                    "parent_tool_use_id": "toolu_task"
+                }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(label(&trace, "agent_id"), Some("a1"));
+        assert_eq!(label(&trace, "agent_type"), Some("Explore"));
+        assert_eq!(label(&trace, "parent_tool_use_id"), Some("toolu_task"));
+    }
+
+    #[test]
+    fn test_stop_captures_turn_messages_only_when_enabled() {
+        use std::io::Write;
+        use tempfile::NamedTempFile;
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"{{"type":"user","message":{{"role":"user","content":"please fix the bug"}}}}"#
+        )
+        .unwrap();
+        writeln!(
+            file,
+            r#"{{"type":"assistant","message":{{"id":"msg_1","content":[{{"type":"text","text":"Fixed it."}}],"usage":{{"output_tokens":3}}}}}}"#
+        )
+        .unwrap();
+        file.flush().unwrap();
+        let stop = || {
+            hook_frame(
+                "Stop",
+                serde_json::json!({ "transcript_path": file.path().to_str().unwrap() }),
+            )
+        };
+        let capture = |capture| MapOptions {
+            capture,
+            max_chars: 6,
+            ..Default::default()
+        };
+
+        let off = from_tap_frame(stop(), &MapOptions::default()).unwrap();
+        assert!(off.inputs.messages_compact.is_empty());
+
+        let content = from_tap_frame(stop(), &capture(CaptureMode::Content)).unwrap();
+        let msgs: Vec<_> = content
+            .inputs
+            .messages_compact
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_str(), m.chars, m.truncated))
+            .collect();
+        assert_eq!(
+            msgs,
+            [
+                ("user", "please", 18, true),
+                ("assistant", "Fixed ", 9, true)
+            ]
+        );
+
+        let meta = from_tap_frame(stop(), &capture(CaptureMode::Metadata)).unwrap();
+        assert_eq!(meta.inputs.messages_compact.len(), 2);
+        assert!(
+            meta.inputs
+                .messages_compact
+                .iter()
+                .all(|m| m.content.is_empty())
+        );
+        assert_eq!(meta.inputs.messages_compact[0].chars, 18);
+
+        let post = from_tap_frame(
+            hook_frame(
+                "PostToolUse",
+                serde_json::json!({ "transcript_path": file.path().to_str().unwrap() }),
+            ),
+            &capture(CaptureMode::Content),
+        )
+        .unwrap();
+        assert!(
+            post.inputs.messages_compact.is_empty(),
+            "only Stop carries messages"
+        );
+    }
+
+    #[test]
+    fn test_tool_calls_follow_tool_results() {
+        use std::io::Write;
+        use tempfile::NamedTempFile;
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            r#"{{"type":"assistant","message":{{"id":"msg_1","content":[{{"type":"tool_use","id":"tu_1","name":"Bash","input":{{"command":"ls"}}}}],"usage":{{"output_tokens":4}}}}}}"#
+        )
+        .unwrap();
+        file.flush().unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let frame = |hook| hook_frame(hook, serde_json::json!({ "transcript_path": path }));
+
+        let pre = from_tap_frame(frame("PreToolUse"), &MapOptions::default()).unwrap();
+        assert_eq!(pre.outputs.tool_calls.len(), 1);
+        assert_eq!(pre.outputs.tool_calls[0].name, "Bash");
+        assert_eq!(pre.outputs.tool_calls[0].args["command"], "ls");
+        assert_eq!(pre.outputs.tool_calls[0].status, "pending");
+
+        writeln!(
+            file,
+            r#"{{"type":"user","message":{{"role":"user","content":[{{"type":"tool_result","tool_use_id":"tu_1","content":"a.txt"}}]}}}}"#
+        )
+        .unwrap();
+        file.flush().unwrap();
+        let post = from_tap_frame(frame("PostToolUse"), &MapOptions::default()).unwrap();
+        assert_eq!(post.outputs.tool_calls[0].status, "success");
+    }
+
+    #[test]
+    fn test_notification_and_pre_compact_labels() {
+        let trace = from_tap_frame(
+            hook_frame(
+                "Notification",
+                serde_json::json!({ "message": "Claude needs your permission to use Bash" }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(trace.event, "notification");
+        assert_eq!(
+            label(&trace, "notification_message"),
+            Some("Claude needs your permission to use Bash")
+        );
+
+        let trace = from_tap_frame(
+            hook_frame(
+                "PreCompact",
+                serde_json::json!({ "trigger": "auto", "custom_instructions": "" }),
+            ),
+            &MapOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(trace.event, "compact.pre");
+        assert_eq!(label(&trace, "compact_trigger"), Some("auto"));
+        assert_eq!(label(&trace, "compact_custom_instructions"), Some("false"));
+    }
 }
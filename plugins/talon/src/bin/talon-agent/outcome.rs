@@ -0,0 +1,321 @@
+//! Classifies how a tool call ended.
+//!
+//! `PostToolUse` reports a tool's result in whatever shape the tool produces: a
+//! string, or an object such as Bash's `{stdout, stderr, interrupted}`. Failed
+//! calls arrive either as `PostToolUseFailure` with an `error` string or as a
+//! result whose text says what went wrong. [`classify`] reduces all of these to
+//! a [`ToolOutcome`] that is recorded on the event as labels:
+//!
+//! - `tool_status`: `success`, `error`, `timeout`, `interrupted` or `denied`,
+//! - `tool_error_class`: a coarse reason for failures (see [`ErrorClass`]),
+//! - `tool_exit_code` and `tool_stderr_chars` for Bash when known.
+
+use crate::schema::TraceV1;
+use serde_json::Value as Json;
+
+/// Label holding the normalized tool status.
+pub const TOOL_STATUS: &str = "tool_status";
+
+/// Label holding the error class of a failed tool call.
+pub const TOOL_ERROR_CLASS: &str = "tool_error_class";
+
+/// Normalized result of a tool call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolStatus {
+    Success,
+    Error,
+    Timeout,
+    Interrupted,
+    Denied,
+}
+
+impl ToolStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Error => "error",
+            Self::Timeout => "timeout",
+            Self::Interrupted => "interrupted",
+            Self::Denied => "denied",
+        }
+    }
+}
+
+/// Coarse reason a tool call failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// A command exited with a non-zero status.
+    ExitCode,
+    Timeout,
+    /// The user interrupted the call.
+    Interrupted,
+    /// Permission was refused by the user or a hook.
+    PermissionDenied,
+    /// A file, path or resource didn't exist.
+    NotFound,
+    /// The tool was given arguments it rejected.
+    InvalidInput,
+    /// Any other failure.
+    ToolError,
+}
+
+impl ErrorClass {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ExitCode => "exit_code",
+            Self::Timeout => "timeout",
+            Self::Interrupted => "interrupted",
+            Self::PermissionDenied => "permission_denied",
+            Self::NotFound => "not_found",
+            Self::InvalidInput => "invalid_input",
+            Self::ToolError => "tool_error",
+        }
+    }
+}
+
+/// How a tool call ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolOutcome {
+    pub status: ToolStatus,
+    /// Set when `status` isn't `Success`.
+    pub error_class: Option<ErrorClass>,
+    /// Bash exit code, when reported.
+    pub exit_code: Option<i64>,
+    /// Length of Bash's stderr in characters, when reported.
+    pub stderr_chars: Option<usize>,
+}
+
+impl ToolOutcome {
+    fn success() -> Self {
+        Self {
+            status: ToolStatus::Success,
+            error_class: None,
+            exit_code: None,
+            stderr_chars: None,
+        }
+    }
+
+    fn failed(status: ToolStatus, class: ErrorClass) -> Self {
+        Self {
+            status,
+            error_class: Some(class),
+            ..Self::success()
+        }
+    }
+
+    /// Records the outcome on `t` as labels.
+    pub fn apply(&self, t: &mut TraceV1) {
+        t.set_label(TOOL_STATUS, self.status.as_str());
+        if let Some(class) = self.error_class {
+            t.set_label(TOOL_ERROR_CLASS, class.as_str());
+        }
+        if let Some(code) = self.exit_code {
+            t.set_label("tool_exit_code", code.to_string());
+        }
+        if let Some(n) = self.stderr_chars {
+            t.set_label("tool_stderr_chars", n.to_string());
+        }
+    }
+}
+
+/// Classifies a `tool.post` payload for the tool named `tool`.
+pub fn classify(tool: &str, payload: &Json) -> ToolOutcome {
+    let response = payload.get("tool_response").unwrap_or(&Json::Null);
+    let flag = |v: &Json, key: &str| v.get(key).and_then(|x| x.as_bool()) == Some(true);
+
+    if flag(payload, "is_interrupt") || flag(response, "interrupted") {
+        return ToolOutcome::failed(ToolStatus::Interrupted, ErrorClass::Interrupted);
+    }
+
+    // `PostToolUseFailure` carries the error text; otherwise look in the result.
+    let error = payload.get("error").and_then(|x| x.as_str());
+    let text = error.or_else(|| response_text(response)).unwrap_or("");
+    let is_error = error.is_some() || flag(response, "is_error") || flag(payload, "is_error");
+
+    // Only failures are checked for denial text; a successful result may quote it.
+    let mut outcome = if is_error && is_denial(text) {
+        ToolOutcome::failed(ToolStatus::Denied, ErrorClass::PermissionDenied)
+    } else if is_error {
+        let class = classify_text(text);
+        let status = if class == ErrorClass::Timeout {
+            ToolStatus::Timeout
+        } else {
+            ToolStatus::Error
+        };
+        ToolOutcome::failed(status, class)
+    } else {
+        ToolOutcome::success()
+    };
+
+    if tool == "Bash" {
+        let code = ["exit_code", "exitCode", "returnCode"]
+            .iter()
+            .find_map(|key| response.get(key).and_then(|x| x.as_i64()))
+            .or_else(|| parse_exit_code(text));
+        if let Some(code) = code {
+            outcome.exit_code = Some(code);
+            if code != 0 && outcome.status == ToolStatus::Success {
+                outcome = ToolOutcome {
+                    exit_code: Some(code),
+                    ..ToolOutcome::failed(ToolStatus::Error, ErrorClass::ExitCode)
+                };
+            }
+        }
+        outcome.stderr_chars = response
+            .get("stderr")
+            .and_then(|x| x.as_str())
+            .map(|s| s.chars().count());
+    }
+    outcome
+}
+
+/// Text of a tool result: the string itself, or an `error`/`content` field.
+fn response_text(response: &Json) -> Option<&str> {
+    match response {
+        Json::String(s) => Some(s),
+        Json::Object(o) => ["error", "content"]
+            .iter()
+            .find_map(|key| o.get(*key).and_then(|x| x.as_str())),
+        _ => None,
+    }
+}
+
+/// Claude Code's messages for refused permission prompts and blocking hooks.
+fn is_denial(text: &str) -> bool {
+    const MARKERS: &[&str] = &[
+        "doesn't want to proceed with this tool use",
+        "Permission to use",
+        "permission denied by",
+        "blocked by hook",
+    ];
+    MARKERS.iter().any(|m| text.contains(m))
+}
+
+fn classify_text(text: &str) -> ErrorClass {
+    let lower = text.to_lowercase();
+    if parse_exit_code(text).is_some() {
+        ErrorClass::ExitCode
+    } else if lower.contains("timed out") || lower.contains("timeout") {
+        ErrorClass::Timeout
+    } else if lower.contains("interrupted") {
+        ErrorClass::Interrupted
+    } else if lower.contains("does not exist")
+        || lower.contains("no such file")
+        || lower.contains("not found")
+    {
+        ErrorClass::NotFound
+    } else if lower.contains("invalid") || lower.contains("inputvalidationerror") {
+        ErrorClass::InvalidInput
+    } else {
+        ErrorClass::ToolError
+    }
+}
+
+/// Reads `N` from Bash's "Exit code N" failure prefix.
+fn parse_exit_code(text: &str) -> Option<i64> {
+    let rest = text
+        .trim_start()
+        .trim_start_matches("Error: ")
+        .strip_prefix("Exit code ")?;
+    let digits: String = rest
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '-')
+        .collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn status(tool: &str, payload: Json) -> (&'static str, Option<&'static str>) {
+        let o = classify(tool, &payload);
+        (o.status.as_str(), o.error_class.map(ErrorClass::as_str))
+    }
+
+    #[test]
+    fn test_bash_results() {
+        let ok = classify(
+            "Bash",
+            &json!({ "tool_response": { "stdout": "hi", "stderr": "warn", "interrupted": false } }),
+        );
+        assert_eq!(ok.status, ToolStatus::Success);
+        assert_eq!(ok.stderr_chars, Some(4));
+
+        let failed = classify(
+            "Bash",
+            &json!({ "error": "Exit code 2\nls: cannot access 'x'" }),
+        );
+        assert_eq!(failed.status, ToolStatus::Error);
+        assert_eq!(failed.error_class, Some(ErrorClass::ExitCode));
+        assert_eq!(failed.exit_code, Some(2));
+
+        assert_eq!(
+            status(
+                "Bash",
+                json!({ "tool_response": { "stdout": "", "exit_code": 1 } })
+            ),
+            ("error", Some("exit_code"))
+        );
+        assert_eq!(
+            status(
+                "Bash",
+                json!({ "tool_response": { "stdout": "", "interrupted": true } })
+            ),
+            ("interrupted", Some("interrupted"))
+        );
+        assert_eq!(
+            status(
+                "Bash",
+                json!({ "error": "Command timed out after 2m 0.0s" })
+            ),
+            ("timeout", Some("timeout"))
+        );
+    }
+
+    #[test]
+    fn test_generic_results() {
+        assert_eq!(
+            status("Read", json!({ "tool_response": "file contents" })),
+            ("success", None)
+        );
+        assert_eq!(
+            status(
+                "Read",
+                json!({ "tool_response": { "is_error": true, "content": "File does not exist." } })
+            ),
+            ("error", Some("not_found"))
+        );
+        assert_eq!(
+            status(
+                "Edit",
+                json!({ "error": "The user doesn't want to proceed with this tool use." })
+            ),
+            ("denied", Some("permission_denied"))
+        );
+        assert_eq!(
+            status(
+                "Grep",
+                json!({ "tool_response": { "content": "outcome.rs: \"blocked by hook\"" } })
+            ),
+            ("success", None),
+            "denial text in a successful result"
+        );
+        assert_eq!(
+            status(
+                "Read",
+                json!({ "tool_response": "Permission to use Bash denied" })
+            ),
+            ("success", None)
+        );
+        assert_eq!(
+            status("WebFetch", json!({ "error": "socket hang up" })),
+            ("error", Some("tool_error"))
+        );
+        assert_eq!(
+            status("Grep", json!({ "is_interrupt": true, "error": "aborted" })),
+            ("interrupted", Some("interrupted"))
+        );
+    }
+}
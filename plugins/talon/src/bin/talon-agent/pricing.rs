@@ -0,0 +1,269 @@
+//! Model pricing and cost computation.
+//!
+//! Prices are USD per million tokens, keyed by model ID. A model matches an
+//! entry if its ID equals the key or starts with the key followed by `-` or `@`,
+//! so `claude-sonnet-4-5` covers `claude-sonnet-4-5-20250929` and Vertex-style
+//! `claude-sonnet-4-5@20250929`. The longest matching key wins.
+//!
+//! The built-in table holds Anthropic list prices. Entries under `[pricing]` in
+//! the config file replace or extend it:
+//!
+//! ```toml
+//! [pricing."claude-sonnet-4-5"]
+//! input = 3.0
+//! output = 15.0
+//! cache_write = 3.75   # optional, defaults to 1.25 × input
+//! cache_read = 0.30    # optional, defaults to 0.1 × input
+//! ```
+
+use crate::schema::TraceV1;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Label naming the pricing entry used for an event's cost.
+pub const PRICING_MODEL: &str = "pricing_model";
+
+/// Per-million-token prices for one model.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelPrice {
+    /// Uncached input tokens.
+    pub input: f64,
+    /// Output tokens.
+    pub output: f64,
+    /// Tokens written to the prompt cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_write: Option<f64>,
+    /// Tokens read from the prompt cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read: Option<f64>,
+}
+
+impl ModelPrice {
+    const fn new(input: f64, output: f64, cache_write: f64, cache_read: f64) -> Self {
+        Self {
+            input,
+            output,
+            cache_write: Some(cache_write),
+            cache_read: Some(cache_read),
+        }
+    }
+
+    fn cache_write(&self) -> f64 {
+        self.cache_write.unwrap_or(self.input * 1.25)
+    }
+
+    fn cache_read(&self) -> f64 {
+        self.cache_read.unwrap_or(self.input * 0.1)
+    }
+}
+
+/// Anthropic list prices.
+const BUILTIN: &[(&str, ModelPrice)] = &[
+    ("claude-opus-4-5", ModelPrice::new(5.0, 25.0, 6.25, 0.50)),
+    ("claude-opus-4-1", ModelPrice::new(15.0, 75.0, 18.75, 1.50)),
+    ("claude-opus-4", ModelPrice::new(15.0, 75.0, 18.75, 1.50)),
+    ("claude-sonnet-4-5", ModelPrice::new(3.0, 15.0, 3.75, 0.30)),
+    ("claude-sonnet-4", ModelPrice::new(3.0, 15.0, 3.75, 0.30)),
+    ("claude-haiku-4-5", ModelPrice::new(1.0, 5.0, 1.25, 0.10)),
+    ("claude-3-7-sonnet", ModelPrice::new(3.0, 15.0, 3.75, 0.30)),
+    ("claude-3-5-sonnet", ModelPrice::new(3.0, 15.0, 3.75, 0.30)),
+    ("claude-3-5-haiku", ModelPrice::new(0.80, 4.0, 1.0, 0.08)),
+    ("claude-3-opus", ModelPrice::new(15.0, 75.0, 18.75, 1.50)),
+    ("claude-3-haiku", ModelPrice::new(0.25, 1.25, 0.30, 0.03)),
+];
+
+/// Built-in prices plus configured overrides.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceTable {
+    entries: BTreeMap<String, ModelPrice>,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        Self::with_overrides(BTreeMap::new())
+    }
+}
+
+impl PriceTable {
+    /// Built-in table with `overrides` replacing or adding entries.
+    pub fn with_overrides(overrides: BTreeMap<String, ModelPrice>) -> Self {
+        let mut entries: BTreeMap<_, _> =
+            BUILTIN.iter().map(|(k, p)| (k.to_string(), *p)).collect();
+        entries.extend(overrides);
+        Self { entries }
+    }
+
+    /// Checks that no price is negative or NaN.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first offending model.
+    pub fn validate(&self) -> Result<()> {
+        for (model, p) in &self.entries {
+            let rates = [p.input, p.output, p.cache_write(), p.cache_read()];
+            if rates.iter().any(|r| r.is_nan() || *r < 0.0) {
+                return Err(anyhow!(
+                    "pricing for `{model}` must be non-negative numbers"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the entry for a model ID. Returns the matched key and its prices.
+    pub fn lookup(&self, model: &str) -> Option<(&str, &ModelPrice)> {
+        self.entries
+            .iter()
+            .filter(|(key, _)| model_matches(model, key))
+            .max_by_key(|(key, _)| key.len())
+            .map(|(k, p)| (k.as_str(), p))
+    }
+
+    /// Fills in `input_cost_usd` and `output_cost_usd` from the event's model and
+    /// token counts. Events that already carry a cost, have no tokens, or use an
+    /// unknown model are left alone.
+    pub fn apply(&self, t: &mut TraceV1) {
+        let m = &t.metrics;
+        if m.input_cost_usd > 0.0 || m.output_cost_usd > 0.0 {
+            return;
+        }
+        if m.prompt_tokens == 0 && m.completion_tokens == 0 {
+            return;
+        }
+        let Some((key, price)) = self.lookup(&t.configuration.model) else {
+            return;
+        };
+
+        let per_token = |n: u32, usd_per_mtok: f64| f64::from(n) * usd_per_mtok / 1_000_000.0;
+        let cached = m
+            .cache_creation_input_tokens
+            .saturating_add(m.cache_read_input_tokens);
+        // Without a breakdown all prompt tokens are billed as uncached input.
+        let uncached = if m.uncached_input_tokens > 0 || cached > 0 {
+            m.uncached_input_tokens
+        } else {
+            m.prompt_tokens
+        };
+        let input = per_token(uncached, price.input)
+            + per_token(m.cache_creation_input_tokens, price.cache_write())
+            + per_token(m.cache_read_input_tokens, price.cache_read());
+        let output = per_token(m.completion_tokens, price.output);
+
+        t.metrics.input_cost_usd = input as f32;
+        t.metrics.output_cost_usd = output as f32;
+        t.set_label(PRICING_MODEL, key.to_string());
+    }
+}
+
+/// Whether `model` is `key` or `key` followed by a `-` or `@` suffix.
+pub fn model_matches(model: &str, key: &str) -> bool {
+    model == key
+        || model
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.starts_with(['-', '@']))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_matches_date_suffix_and_longest_prefix() {
+        let table = PriceTable::default();
+        let key = |m: &str| table.lookup(m).map(|(k, _)| k.to_string());
+
+        assert_eq!(
+            key("claude-sonnet-4-5-20250929").as_deref(),
+            Some("claude-sonnet-4-5")
+        );
+        assert_eq!(
+            key("claude-sonnet-4-20250514").as_deref(),
+            Some("claude-sonnet-4")
+        );
+        assert_eq!(
+            key("claude-opus-4-1@20250805").as_deref(),
+            Some("claude-opus-4-1")
+        );
+        assert_eq!(key("claude-haiku-4-5").as_deref(), Some("claude-haiku-4-5"));
+        assert_eq!(
+            key("claude-sonnet-45"),
+            None,
+            "prefix must end at a separator"
+        );
+        assert_eq!(key("gpt-4o"), None);
+    }
+
+    #[test]
+    fn test_cost_uses_cache_rates() {
+        let mut t = TraceV1::default();
+        t.configuration.model = "claude-sonnet-4-5-20250929".to_string();
+        t.metrics.prompt_tokens = 1_500_000;
+        t.metrics.uncached_input_tokens = 1_000_000;
+        t.metrics.cache_creation_input_tokens = 100_000;
+        t.metrics.cache_read_input_tokens = 400_000;
+        t.metrics.completion_tokens = 200_000;
+
+        PriceTable::default().apply(&mut t);
+        // 3.00 + 0.375 + 0.12 input, 3.00 output
+        assert!((t.metrics.input_cost_usd - 3.495).abs() < 1e-4);
+        assert!((t.metrics.output_cost_usd - 3.0).abs() < 1e-4);
+        assert_eq!(t.label(PRICING_MODEL), Some("claude-sonnet-4-5"));
+    }
+
+    #[test]
+    fn test_overrides_replace_and_extend_builtins() {
+        let overrides = BTreeMap::from([
+            (
+                "claude-sonnet-4-5".to_string(),
+                ModelPrice {
+                    input: 1.0,
+                    output: 2.0,
+                    cache_write: None,
+                    cache_read: None,
+                },
+            ),
+            (
+                "my-model".to_string(),
+                ModelPrice::new(10.0, 20.0, 0.0, 0.0),
+            ),
+        ]);
+        let table = PriceTable::with_overrides(overrides);
+
+        let mut t = TraceV1::default();
+        t.configuration.model = "claude-sonnet-4-5".to_string();
+        t.metrics.prompt_tokens = 1_000_000;
+        t.metrics.cache_read_input_tokens = 1_000_000;
+        table.apply(&mut t);
+        assert!(
+            (t.metrics.input_cost_usd - 0.1).abs() < 1e-6,
+            "default cache read rate"
+        );
+
+        let mut t = TraceV1::default();
+        t.configuration.model = "my-model-v2".to_string();
+        t.metrics.prompt_tokens = 1_000_000;
+        table.apply(&mut t);
+        assert!(
+            (t.metrics.input_cost_usd - 10.0).abs() < 1e-4,
+            "prompt billed as input"
+        );
+    }
+
+    #[test]
+    fn test_unknown_model_and_existing_cost_are_left_alone() {
+        let table = PriceTable::default();
+        let mut t = TraceV1::default();
+        t.configuration.model = "unknown".to_string();
+        t.metrics.prompt_tokens = 100;
+        table.apply(&mut t);
+        assert_eq!(t.metrics.input_cost_usd, 0.0);
+        assert_eq!(t.label(PRICING_MODEL), None);
+
+        t.configuration.model = "claude-haiku-4-5".to_string();
+        t.metrics.output_cost_usd = 9.0;
+        table.apply(&mut t);
+        assert_eq!(t.metrics.input_cost_usd, 0.0);
+    }
+}
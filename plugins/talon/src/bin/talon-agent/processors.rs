@@ -0,0 +1,57 @@
+//! Filters applied to outgoing events, set in the `[processors]` config table.
+//!
+//! They run last, after session rollups have counted the event, so dropping
+//! events or labels never changes what `session.summary` reports.
+
+use crate::schema::TraceV1;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Processors {
+    /// Canonical event names that are never sent.
+    pub drop_events: BTreeSet<String>,
+    /// Label keys removed from every event before it is sent.
+    pub drop_labels: BTreeSet<String>,
+}
+
+impl Processors {
+    /// Whether `t` is sent at all.
+    pub fn keeps(&self, t: &TraceV1) -> bool {
+        !self.drop_events.contains(&t.event)
+    }
+
+    /// Removes the dropped labels from an event that is sent.
+    pub fn apply(&self, t: &mut TraceV1) {
+        if !self.drop_labels.is_empty() {
+            t.labels.retain(|l| !self.drop_labels.contains(&l.key));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drops_configured_events_and_labels() {
+        let processors = Processors {
+            drop_events: ["tool.pre".to_string()].into(),
+            drop_labels: ["git_branch".to_string()].into(),
+        };
+        let mut t = TraceV1 {
+            event: "tool.post".to_string(),
+            ..Default::default()
+        };
+        t.set_label("git_branch", "main");
+        t.set_label("tool_status", "success");
+
+        assert!(processors.keeps(&t));
+        processors.apply(&mut t);
+        assert_eq!(t.label("git_branch"), None);
+        assert_eq!(t.label("tool_status"), Some("success"));
+
+        t.event = "tool.pre".to_string();
+        assert!(!processors.keeps(&t));
+    }
+}
@@ -0,0 +1,316 @@
+//! Per-session rollups, sent as `session.summary` events.
+//!
+//! Every outgoing event is added to its session's totals after usage attribution
+//! and pricing, so tokens and cost are counted once. When a turn ends (a `turn`
+//! span, or `agent.stop` without turn tracking) a running summary is emitted, and
+//! `session.end` emits the final one and forgets the session. Downstream, the
+//! latest summary of a session supersedes earlier ones.
+//!
+//! Totals are carried in `session_*` labels rather than metrics, so summing
+//! metrics over all events isn't thrown off; breakdowns by tool and error status
+//! are in `extensions.session_summary`.
+
+use crate::{
+    schema::{Ctx, TraceV1},
+    spans::{SPAN_STATUS, TURN_EVENT},
+};
+use chrono::{DateTime, FixedOffset};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Canonical event name of session summaries.
+pub const SUMMARY_EVENT: &str = "session.summary";
+
+/// Sessions tracked at once; the least recently active is forgotten first.
+const MAX_SESSIONS: usize = 1024;
+
+/// Distinct tool names, models and files kept per session.
+const MAX_TOOLS: usize = 200;
+const MAX_MODELS: usize = 16;
+const MAX_FILES: usize = 1000;
+
+#[derive(Default)]
+struct Session {
+    start: Option<DateTime<FixedOffset>>,
+    end: Option<DateTime<FixedOffset>>,
+    /// Context of the latest event, reused for the summary.
+    context: Ctx,
+    turns: u32,
+    tool_calls: BTreeMap<String, u32>,
+    /// Failed tool calls by `tool_status`.
+    errors: BTreeMap<String, u32>,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    uncached_input_tokens: u64,
+    cache_creation_input_tokens: u64,
+    cache_read_input_tokens: u64,
+    cost_usd: f64,
+    models: BTreeSet<String>,
+    /// Latest model seen.
+    model: String,
+    files: BTreeSet<String>,
+    last_seen: u64,
+}
+
+impl Session {
+    fn observe(&mut self, t: &TraceV1) {
+        if let Ok(start) = DateTime::parse_from_rfc3339(&t.timestamp) {
+            // Spans are stamped with their start; they end after their duration.
+            let end = start + chrono::Duration::milliseconds(i64::from(t.metrics.latency_ms.total));
+            self.start = Some(self.start.map_or(start, |s| s.min(start)));
+            self.end = Some(self.end.map_or(end, |e| e.max(end)));
+        }
+        self.context = t.context.clone();
+
+        match t.event.as_str() {
+            TURN_EVENT | "agent.stop" => self.turns += 1,
+            "tool.post" => self.count_tool(t),
+            "tool.pre" if t.label(SPAN_STATUS) == Some("incomplete") => self.count_tool(t),
+            _ => {}
+        }
+
+        let m = &t.metrics;
+        self.prompt_tokens += u64::from(m.prompt_tokens);
+        self.completion_tokens += u64::from(m.completion_tokens);
+        self.uncached_input_tokens += u64::from(m.uncached_input_tokens);
+        self.cache_creation_input_tokens += u64::from(m.cache_creation_input_tokens);
+        self.cache_read_input_tokens += u64::from(m.cache_read_input_tokens);
+        self.cost_usd += f64::from(m.total_cost_usd);
+
+        let model = &t.configuration.model;
+        if !model.is_empty() {
+            if self.models.len() < MAX_MODELS {
+                self.models.insert(model.clone());
+            }
+            self.model = model.clone();
+        }
+        for file in &t.inputs.files {
+            if self.files.len() >= MAX_FILES {
+                break;
+            }
+            self.files.insert(file.clone());
+        }
+    }
+
+    fn count_tool(&mut self, t: &TraceV1) {
+        let name = match t.inputs.tool.name.as_str() {
+            "" => "unknown",
+            name => name,
+        };
+        if self.tool_calls.contains_key(name) || self.tool_calls.len() < MAX_TOOLS {
+            *self.tool_calls.entry(name.to_string()).or_default() += 1;
+        }
+        if let Some(status) = t.label("tool_status")
+            && status != "success"
+        {
+            *self.errors.entry(status.to_string()).or_default() += 1;
+        }
+    }
+
+    fn summary(&self, session_id: &str, is_final: bool) -> TraceV1 {
+        let mut t = TraceV1 {
+            event: SUMMARY_EVENT.to_string(),
+            timestamp: self.start.map(|s| s.to_rfc3339()).unwrap_or_default(),
+            context: self.context.clone(),
+            ..Default::default()
+        };
+        t.ids.session_id = session_id.to_string();
+        t.configuration.model = self.model.clone();
+        t.inputs.files = self.files.iter().cloned().collect();
+
+        let duration_ms = match (self.start, self.end) {
+            (Some(s), Some(e)) => (e - s).num_milliseconds().max(0) as u64,
+            _ => 0,
+        };
+        t.metrics.latency_ms.total = duration_ms.min(u64::from(u32::MAX)) as u32;
+
+        let tool_calls: u32 = self.tool_calls.values().sum();
+        let errors: u32 = self.errors.values().sum();
+        let total_tokens = self.prompt_tokens + self.completion_tokens;
+        let models: Vec<_> = self.models.iter().cloned().collect();
+        for (key, value) in [
+            ("session_summary_final", is_final.to_string()),
+            ("session_duration_ms", duration_ms.to_string()),
+            ("session_turns", self.turns.to_string()),
+            ("session_tool_calls", tool_calls.to_string()),
+            ("session_tool_errors", errors.to_string()),
+            ("session_prompt_tokens", self.prompt_tokens.to_string()),
+            (
+                "session_completion_tokens",
+                self.completion_tokens.to_string(),
+            ),
+            ("session_total_tokens", total_tokens.to_string()),
+            (
+                "session_uncached_input_tokens",
+                self.uncached_input_tokens.to_string(),
+            ),
+            (
+                "session_cache_creation_input_tokens",
+                self.cache_creation_input_tokens.to_string(),
+            ),
+            (
+                "session_cache_read_input_tokens",
+                self.cache_read_input_tokens.to_string(),
+            ),
+            ("session_cost_usd", format!("{:.6}", self.cost_usd)),
+            ("session_models", models.join(",")),
+        ] {
+            t.set_label(key, value);
+        }
+
+        t.extensions = serde_json::json!({
+            "session_summary": {
+                "final": is_final,
+                "duration_ms": duration_ms,
+                "turns": self.turns,
+                "tool_calls": self.tool_calls,
+                "tool_errors": self.errors,
+                "tokens": {
+                    "prompt": self.prompt_tokens,
+                    "completion": self.completion_tokens,
+                    "total": total_tokens,
+                    "uncached_input": self.uncached_input_tokens,
+                    "cache_creation_input": self.cache_creation_input_tokens,
+                    "cache_read_input": self.cache_read_input_tokens
+                },
+                "cost_usd": self.cost_usd,
+                "models": models,
+                "files": self.files.len()
+            }
+        });
+        t
+    }
+}
+
+/// Running aggregates of recent sessions.
+#[derive(Default)]
+pub struct SessionRollup {
+    sessions: HashMap<String, Session>,
+    /// Monotonic counter used to order sessions by recency.
+    clock: u64,
+}
+
+impl SessionRollup {
+    /// Adds an outgoing event to its session and returns the summary to send
+    /// after it, if the event ends a turn or the session.
+    pub fn observe(&mut self, t: &TraceV1) -> Option<TraceV1> {
+        let id = &t.ids.session_id;
+        if id.is_empty() || t.event == SUMMARY_EVENT {
+            return None;
+        }
+        self.clock += 1;
+        if !self.sessions.contains_key(id) && self.sessions.len() >= MAX_SESSIONS {
+            self.forget_oldest();
+        }
+        let session = self.sessions.entry(id.clone()).or_default();
+        session.last_seen = self.clock;
+        session.observe(t);
+
+        match t.event.as_str() {
+            "session.end" => self.sessions.remove(id).map(|s| s.summary(id, true)),
+            TURN_EVENT | "agent.stop" => Some(session.summary(id, false)),
+            _ => None,
+        }
+    }
+
+    fn forget_oldest(&mut self) {
+        if let Some(oldest) = self
+            .sessions
+            .iter()
+            .min_by_key(|(_, s)| s.last_seen)
+            .map(|(k, _)| k.clone())
+        {
+            self.sessions.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, ts: &str) -> TraceV1 {
+        let mut t = TraceV1 {
+            event: name.to_string(),
+            timestamp: ts.to_string(),
+            ..Default::default()
+        };
+        t.ids.session_id = "s".to_string();
+        t
+    }
+
+    fn tool(name: &str, status: &str, ts: &str) -> TraceV1 {
+        let mut t = event("tool.post", ts);
+        t.inputs.tool.name = name.to_string();
+        t.set_label("tool_status", status);
+        t.metrics.latency_ms.total = 500;
+        t
+    }
+
+    #[test]
+    fn test_session_end_emits_the_final_summary() {
+        let mut rollup = SessionRollup::default();
+        assert!(
+            rollup
+                .observe(&event("session.start", "2025-11-13T10:00:00Z"))
+                .is_none()
+        );
+
+        let mut read = tool("Read", "success", "2025-11-13T10:00:01Z");
+        read.inputs.files = vec!["src/lib.rs".to_string()];
+        read.configuration.model = "claude-sonnet-4-5".to_string();
+        read.metrics.prompt_tokens = 1000;
+        read.metrics.uncached_input_tokens = 200;
+        read.metrics.cache_read_input_tokens = 800;
+        read.metrics.completion_tokens = 50;
+        read.metrics.total_cost_usd = 0.01;
+        rollup.observe(&read);
+        rollup.observe(&tool("Bash", "error", "2025-11-13T10:00:02Z"));
+        rollup.observe(&tool("Bash", "success", "2025-11-13T10:00:03Z"));
+
+        let running = rollup
+            .observe(&event(TURN_EVENT, "2025-11-13T10:00:00.500Z"))
+            .unwrap();
+        assert_eq!(running.label("session_summary_final"), Some("false"));
+        assert_eq!(running.label("session_turns"), Some("1"));
+
+        let summary = rollup
+            .observe(&event("session.end", "2025-11-13T10:01:00Z"))
+            .unwrap();
+        assert_eq!(summary.event, SUMMARY_EVENT);
+        assert_eq!(summary.ids.session_id, "s");
+        assert_eq!(summary.timestamp, "2025-11-13T10:00:00+00:00");
+        assert_eq!(summary.metrics.latency_ms.total, 60_000);
+        assert_eq!(
+            summary.metrics.total_tokens, 0,
+            "totals stay out of metrics"
+        );
+        assert_eq!(summary.label("session_summary_final"), Some("true"));
+        assert_eq!(summary.label("session_tool_calls"), Some("3"));
+        assert_eq!(summary.label("session_tool_errors"), Some("1"));
+        assert_eq!(summary.label("session_total_tokens"), Some("1050"));
+        assert_eq!(
+            summary.label("session_cache_read_input_tokens"),
+            Some("800")
+        );
+        assert_eq!(summary.label("session_models"), Some("claude-sonnet-4-5"));
+        assert_eq!(summary.inputs.files, ["src/lib.rs"]);
+        let detail = &summary.extensions["session_summary"];
+        assert_eq!(detail["tool_calls"]["Bash"], 2);
+        assert_eq!(detail["tool_errors"]["error"], 1);
+        assert!(rollup.sessions.is_empty());
+    }
+
+    #[test]
+    fn test_summaries_and_sessionless_events_are_not_counted() {
+        let mut rollup = SessionRollup::default();
+        let mut bare = tool("Read", "success", "");
+        bare.ids.session_id.clear();
+        assert!(rollup.observe(&bare).is_none());
+
+        let summary = rollup.observe(&event("agent.stop", "")).unwrap();
+        assert!(rollup.observe(&summary).is_none());
+        let again = rollup.observe(&event("agent.stop", "")).unwrap();
+        assert_eq!(again.label("session_turns"), Some("2"));
+        assert_eq!(again.label("session_tool_calls"), Some("0"));
+    }
+}
@@ -71,14 +71,37 @@ pub struct TraceV1 {
     pub extensions: serde_json::Value,
 }
 
+impl TraceV1 {
+    /// Value of the first label with `key`.
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|l| l.key == key)
+            .map(|l| l.value.as_str())
+    }
+
+    /// Sets a label, replacing any existing value for `key`.
+    pub fn set_label(&mut self, key: &str, value: impl Into<String>) {
+        let value = value.into();
+        match self.labels.iter_mut().find(|l| l.key == key) {
+            Some(l) => l.value = value,
+            None => self.labels.push(Label {
+                key: key.to_string(),
+                value,
+            }),
+        }
+    }
+}
+
 /// Correlation identifiers for distributed tracing.
 ///
 /// Links events across process boundaries and time, forming hierarchical traces
 /// and conversation threads.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Ids {
-    /// Unique trace identifier shared by all events in a request flow.
-    /// UUID v4 format. Generated by [`canonicalize`] if empty.
+    /// Trace identifier shared by all events in a request flow.
+    /// Generated by [`canonicalize`] if empty: derived from `session_id` so a
+    /// session forms one trace, or a random UUID v4 for events without a session.
     #[serde(default)]
     pub trace_id: String,
 
@@ -87,7 +110,8 @@ pub struct Ids {
     #[serde(default)]
     pub span_id: String,
 
-    /// Parent span ID. Empty for root events.
+    /// Parent span ID. Empty for root events. [`canonicalize`] parents
+    /// session events under the session's root span.
     #[serde(default)]
     pub parent_span_id: String,
 
@@ -95,6 +119,11 @@ pub struct Ids {
     #[serde(default)]
     pub conversation_id: String,
 
+    /// Anthropic message ID (`msg_...`) of the transcript message whose usage
+    /// this event carries. Empty if the event wasn't enriched from a transcript.
+    #[serde(default)]
+    pub message_id: String,
+
     /// Session identifier. Typically spans multiple conversations.
     #[serde(default)]
     pub session_id: String,
@@ -176,6 +205,11 @@ pub struct Inputs {
     /// Retrieved context items from RAG (Retrieval-Augmented Generation).
     #[serde(default)]
     pub retrieval_items: Vec<RetrievalItem>,
+
+    /// Files read or modified by the operation, relative to the session's working
+    /// directory when inside it. Deduplicated.
+    #[serde(default)]
+    pub files: Vec<String>,
 }
 
 /// Output data produced by the operation.
@@ -231,6 +265,19 @@ pub struct Metrics {
     #[serde(default)]
     pub total_tokens: u32,
 
+    /// Prompt tokens neither written to nor read from the prompt cache.
+    /// Together with the two cache fields this adds up to `prompt_tokens`.
+    #[serde(default)]
+    pub uncached_input_tokens: u32,
+
+    /// Prompt tokens written to the prompt cache.
+    #[serde(default)]
+    pub cache_creation_input_tokens: u32,
+
+    /// Prompt tokens served from the prompt cache.
+    #[serde(default)]
+    pub cache_read_input_tokens: u32,
+
     /// Whether token counts are estimated rather than exact.
     #[serde(default)]
     pub token_counts_estimated: bool,
@@ -258,6 +305,22 @@ pub struct Metrics {
     /// Quality score for the output. Range and interpretation are application-specific.
     #[serde(default)]
     pub quality_score: f32,
+
+    /// Lines added by a code-changing tool call (Edit, MultiEdit, Write).
+    #[serde(default)]
+    pub lines_added: u32,
+
+    /// Lines removed by a code-changing tool call.
+    #[serde(default)]
+    pub lines_removed: u32,
+
+    /// Bytes of the added lines.
+    #[serde(default)]
+    pub bytes_added: u32,
+
+    /// Bytes of the removed lines.
+    #[serde(default)]
+    pub bytes_removed: u32,
 }
 
 /// Latency measurements in milliseconds at different stages of processing.
@@ -303,9 +366,17 @@ pub struct Message {
     #[serde(default)]
     pub role: String,
 
-    /// Message content (text).
+    /// Message content (text). Empty when only metadata was captured.
     #[serde(default)]
     pub content: String,
+
+    /// Length of the original content in characters.
+    #[serde(default)]
+    pub chars: u32,
+
+    /// Whether `content` was cut short of the original.
+    #[serde(default)]
+    pub truncated: bool,
 }
 
 /// Tool invocation metadata.
@@ -360,6 +431,56 @@ pub struct RetrievalItem {
     pub size_tokens: u32,
 }
 
+/// Deterministic UUID for `key` within a namespace (`kind`).
+///
+/// Uses two FNV-1a passes with different offsets for 128 bits, so the same key
+/// yields the same ID across agent restarts and builds. Not cryptographic; the IDs
+/// only need to be stable and well spread. Formatted as a UUID v8.
+fn stable_id(kind: &str, key: &str) -> String {
+    fn fnv1a(offset: u64, parts: &[&[u8]]) -> u64 {
+        let mut h = offset;
+        for byte in parts.iter().flat_map(|p| p.iter()) {
+            h ^= u64::from(*byte);
+            h = h.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+        h
+    }
+    let parts: [&[u8]; 3] = [kind.as_bytes(), &[0], key.as_bytes()];
+    let hi = fnv1a(0xcbf2_9ce4_8422_2325, &parts);
+    let lo = fnv1a(0x6c62_272e_07bb_0142, &parts);
+
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&hi.to_be_bytes());
+    bytes[8..].copy_from_slice(&lo.to_be_bytes());
+    uuid::Builder::from_custom_bytes(bytes)
+        .into_uuid()
+        .to_string()
+}
+
+/// Trace ID shared by every event of a session.
+pub fn session_trace_id(session_id: &str) -> String {
+    stable_id("talon.trace", session_id)
+}
+
+/// Span ID of a session's root span. The `session.start` that begins a session
+/// takes this ID; other events of the session are parented under it.
+pub fn session_root_span_id(session_id: &str) -> String {
+    stable_id("talon.session", session_id)
+}
+
+/// Whether `t` is the `session.start` that begins its session.
+///
+/// Claude Code fires `SessionStart` again under the same session ID when a
+/// session is resumed or compacted (`source` `resume` or `compact`); those are
+/// ordinary children of the root rather than the root itself.
+fn starts_session(t: &TraceV1) -> bool {
+    t.event == "session.start"
+        && matches!(
+            t.label("session_source"),
+            None | Some("startup") | Some("clear")
+        )
+}
+
 /// Normalizes a trace event to canonical form.
 ///
 /// Ensures trace events are well-formed before serialization. Idempotent.
@@ -367,7 +488,10 @@ pub struct RetrievalItem {
 /// Sets defaults if empty:
 /// - `schema_version`: `"beak.trace.v1"`
 /// - `timestamp`: Current UTC timestamp (ISO8601)
-/// - `trace_id` and `span_id`: UUID v4
+/// - `trace_id`: [`session_trace_id`] when `session_id` is set, else UUID v4
+/// - `span_id`: [`session_root_span_id`] for a `session.start` that begins the
+///   session (see [`starts_session`]), else UUID v4
+/// - `parent_span_id`: the session root span, for other events with a session
 /// - `total_tokens`: Sum of prompt and completion tokens
 /// - `total_cost_usd`: Sum of input and output costs
 /// - `extensions`: Empty JSON object if not already an object
@@ -381,8 +505,21 @@ pub fn canonicalize(t: &mut TraceV1) {
     if t.timestamp.is_empty() {
         t.timestamp = Utc::now().to_rfc3339();
     }
+    let session = t.ids.session_id.as_str();
     if t.ids.trace_id.is_empty() {
-        t.ids.trace_id = Uuid::new_v4().to_string();
+        t.ids.trace_id = if session.is_empty() {
+            Uuid::new_v4().to_string()
+        } else {
+            session_trace_id(session)
+        };
+    }
+    if !session.is_empty() {
+        let root = session_root_span_id(session);
+        if t.ids.span_id.is_empty() && starts_session(t) {
+            t.ids.span_id = root;
+        } else if t.ids.parent_span_id.is_empty() && t.ids.span_id != root {
+            t.ids.parent_span_id = root;
+        }
     }
     if t.ids.span_id.is_empty() {
         t.ids.span_id = Uuid::new_v4().to_string();
@@ -404,3 +541,90 @@ pub fn canonicalize(t: &mut TraceV1) {
         t.extensions = serde_json::json!({});
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, session: &str) -> TraceV1 {
+        let mut t = TraceV1 {
+            event: name.to_string(),
+            ..Default::default()
+        };
+        t.ids.session_id = session.to_string();
+        t
+    }
+
+    #[test]
+    fn test_session_events_share_trace_and_root() {
+        let mut start = event("session.start", "sess-1");
+        let mut tool = event("tool.post", "sess-1");
+        let mut other = event("tool.post", "sess-2");
+        for t in [&mut start, &mut tool, &mut other] {
+            canonicalize(t);
+        }
+
+        assert_eq!(start.ids.trace_id, tool.ids.trace_id);
+        assert_ne!(tool.ids.trace_id, other.ids.trace_id);
+        assert_eq!(start.ids.trace_id, session_trace_id("sess-1"));
+
+        assert_eq!(start.ids.span_id, session_root_span_id("sess-1"));
+        assert_eq!(start.ids.parent_span_id, "");
+        assert_eq!(tool.ids.parent_span_id, start.ids.span_id);
+        assert_ne!(tool.ids.span_id, start.ids.span_id);
+    }
+
+    #[test]
+    fn test_repeated_session_start_is_a_child_of_the_root() {
+        let mut first = event("session.start", "sess-1");
+        first.set_label("session_source", "startup");
+        let mut compact = event("session.start", "sess-1");
+        compact.set_label("session_source", "compact");
+        let mut resume = event("session.start", "sess-1");
+        resume.set_label("session_source", "resume");
+        for t in [&mut first, &mut compact, &mut resume] {
+            canonicalize(t);
+        }
+
+        let root = session_root_span_id("sess-1");
+        assert_eq!(first.ids.span_id, root);
+        for later in [&compact, &resume] {
+            assert_eq!(later.ids.parent_span_id, root);
+            assert_ne!(later.ids.span_id, root);
+        }
+        assert_ne!(compact.ids.span_id, resume.ids.span_id);
+    }
+
+    #[test]
+    fn test_ids_are_stable_uuids() {
+        let id = session_trace_id("sess-1");
+        assert_eq!(id, session_trace_id("sess-1"));
+        assert_ne!(id, session_root_span_id("sess-1"));
+        let parsed = uuid::Uuid::parse_str(&id).unwrap();
+        assert_eq!(parsed.get_version_num(), 8);
+    }
+
+    #[test]
+    fn test_canonicalize_without_session_uses_random_ids() {
+        let mut a = event("tool.post", "");
+        let mut b = event("tool.post", "");
+        canonicalize(&mut a);
+        canonicalize(&mut b);
+        assert_ne!(a.ids.trace_id, b.ids.trace_id);
+        assert_eq!(a.ids.parent_span_id, "");
+    }
+
+    #[test]
+    fn test_canonicalize_keeps_explicit_ids_and_is_idempotent() {
+        let mut t = event("tool.post", "sess-1");
+        t.ids.trace_id = "given-trace".into();
+        t.ids.parent_span_id = "given-parent".into();
+        canonicalize(&mut t);
+        let first = t.ids.clone();
+        canonicalize(&mut t);
+
+        assert_eq!(t.ids.trace_id, "given-trace");
+        assert_eq!(t.ids.parent_span_id, "given-parent");
+        assert_eq!(t.ids.span_id, first.span_id);
+    }
+}
@@ -0,0 +1,265 @@
+//! Collector API key sources.
+//!
+//! The key never travels on argv. It comes from exactly one of:
+//! - `TRACE_API_KEY` in the agent's environment (or `api_key` in the config file),
+//! - a file (`--api-key-file`), re-read every refresh interval so rotation is picked up,
+//! - a credential helper command (`--api-key-cmd`), whose trimmed stdout is the key.
+//!
+//! File and command results are cached for the refresh interval. The cache is
+//! dropped when the collector rejects the credentials, so the next send re-reads
+//! the file or re-runs the helper. If a refresh fails, the previous key is kept.
+
+use anyhow::{Context, Result, anyhow};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long a credential helper may run before it is killed.
+const HELPER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the API key comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    /// Key given directly (environment or config file).
+    Value(String),
+    /// File whose trimmed contents are the key.
+    File(PathBuf),
+    /// Shell command whose trimmed stdout is the key.
+    Command(String),
+}
+
+impl KeySource {
+    /// Short name for `status` output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            KeySource::Value(_) => "value",
+            KeySource::File(_) => "file",
+            KeySource::Command(_) => "command",
+        }
+    }
+}
+
+/// A resolvable, cached API key.
+#[derive(Debug)]
+pub struct ApiKey {
+    source: KeySource,
+    refresh: Duration,
+    cached: Mutex<Option<(String, Instant)>>,
+}
+
+impl ApiKey {
+    pub fn new(source: KeySource, refresh: Duration) -> Self {
+        Self {
+            source,
+            refresh,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub fn source(&self) -> &KeySource {
+        &self.source
+    }
+
+    /// Returns the current key, reading the file or running the helper if the
+    /// cached value is missing or older than the refresh interval.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key can't be obtained and no earlier value is cached.
+    pub fn get(&self) -> Result<String> {
+        if let KeySource::Value(key) = &self.source {
+            return Ok(key.clone());
+        }
+
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((key, at)) = cached.as_ref()
+            && at.elapsed() < self.refresh
+        {
+            return Ok(key.clone());
+        }
+
+        let fresh = match &self.source {
+            KeySource::Value(key) => Ok(key.clone()),
+            KeySource::File(path) => read_key_file(path),
+            KeySource::Command(cmd) => run_helper(cmd, HELPER_TIMEOUT),
+        };
+        match (fresh, cached.as_mut()) {
+            (Ok(key), _) => {
+                *cached = Some((key.clone(), Instant::now()));
+                Ok(key)
+            }
+            (Err(e), Some((stale, at))) => {
+                eprintln!("talon-agent: API key refresh failed, keeping previous key: {e:#}");
+                // Back off for a full interval rather than retrying on every send.
+                *at = Instant::now();
+                Ok(stale.clone())
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    /// Drops the cached key so the next [`get`](Self::get) fetches a fresh one.
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+fn read_key_file(path: &Path) -> Result<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(meta) = std::fs::metadata(path)
+            && meta.permissions().mode() & 0o077 != 0
+        {
+            eprintln!(
+                "talon-agent: warning: API key file {} is readable by other users",
+                path.display()
+            );
+        }
+    }
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("read API key file {}", path.display()))?;
+    non_empty(text, || format!("API key file {} is empty", path.display()))
+}
+
+/// Runs a credential helper through the shell and returns its trimmed stdout.
+///
+/// Stderr is included in the error on failure; stdout never is, since it may
+/// hold a partial secret.
+fn run_helper(cmd: &str, timeout: Duration) -> Result<String> {
+    #[cfg(unix)]
+    let mut command = {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(cmd);
+        c
+    };
+    #[cfg(not(unix))]
+    let mut command = {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(cmd);
+        c
+    };
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("run API key command `{cmd}`"))?;
+
+    // Read pipes on their own threads so a chatty helper can't block on a full pipe.
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut out = String::new();
+            if let Some(mut p) = pipe {
+                let _ = p.read_to_string(&mut out);
+            }
+            out
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as _));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!(
+                "API key command `{cmd}` timed out after {timeout:?}"
+            ));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        return Err(anyhow!(
+            "API key command `{cmd}` failed ({status}): {}",
+            stderr.trim()
+        ));
+    }
+    non_empty(stdout, || {
+        format!("API key command `{cmd}` printed nothing")
+    })
+}
+
+fn non_empty(text: String, err: impl FnOnce() -> String) -> Result<String> {
+    let key = text.trim();
+    if key.is_empty() {
+        return Err(anyhow!(err()));
+    }
+    Ok(key.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_file_key_is_trimmed_and_refreshed_after_invalidate() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("key");
+        std::fs::write(&path, "first\n").unwrap();
+
+        let key = ApiKey::new(KeySource::File(path.clone()), Duration::from_secs(3600));
+        assert_eq!(key.get().unwrap(), "first");
+
+        std::fs::write(&path, "second\n").unwrap();
+        assert_eq!(key.get().unwrap(), "first", "cached until refresh");
+        key.invalidate();
+        assert_eq!(key.get().unwrap(), "second");
+    }
+
+    #[test]
+    fn test_failed_refresh_keeps_previous_key() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("key");
+        std::fs::write(&path, "k1").unwrap();
+
+        let key = ApiKey::new(KeySource::File(path.clone()), Duration::ZERO);
+        assert_eq!(key.get().unwrap(), "k1");
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(key.get().unwrap(), "k1");
+
+        key.invalidate();
+        assert!(key.get().is_err(), "nothing cached to fall back on");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_key_is_cached() {
+        let temp = TempDir::new().unwrap();
+        let runs = temp.path().join("runs");
+        let cmd = format!("echo run >> '{}'; echo ' s3cret '", runs.display());
+
+        let key = ApiKey::new(KeySource::Command(cmd), Duration::from_secs(3600));
+        assert_eq!(key.get().unwrap(), "s3cret");
+        assert_eq!(key.get().unwrap(), "s3cret");
+        let count = std::fs::read_to_string(&runs).unwrap().lines().count();
+        assert_eq!(count, 1, "helper should run once while cached");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_failures() {
+        let err = run_helper("echo oops >&2; exit 3", HELPER_TIMEOUT).unwrap_err();
+        assert!(err.to_string().contains("oops"), "{err}");
+
+        assert!(run_helper("true", HELPER_TIMEOUT).is_err(), "empty output");
+
+        let err = run_helper("sleep 5", Duration::from_millis(100)).unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err}");
+    }
+}
@@ -0,0 +1,1079 @@
+//! Builds the span tree from hook events: tool, turn, subagent and compaction spans.
+//!
+//! **Tool spans.** Claude Code fires `PreToolUse` before a tool runs and
+//! `PostToolUse` after, both carrying the same `tool_use_id`. The tracker holds
+//! each `tool.pre` event until its `tool.post` arrives and then emits a single
+//! span: start timestamp from the pre event, duration from the two hook
+//! timestamps, inputs from whichever side has them and outputs from the post event.
+//!
+//! **Turn spans.** `UserPromptSubmit` opens a turn for its session and `Stop`
+//! closes it. Every event of the session in between is parented under the turn,
+//! and the closing `Stop` becomes a `turn` span covering the whole agent loop,
+//! with the model, the turn's tokens (summed once per transcript message) and the
+//! number of tool calls.
+//!
+//! **Subagent spans.** A `Task` tool call runs a subagent, whose hooks fire in the
+//! same session between the Task's `PreToolUse` and `PostToolUse`. Events that
+//! belong to an open Task are parented under its span instead of the turn and
+//! labelled with `subagent_type` and `parent_tool_use_id`; the Task span gets the
+//! subagent's token total and tool call count. An event belongs to a Task when
+//! its payload names the Task's `tool_use_id` (`parent_tool_use_id`), when its
+//! `agent_type` matches exactly one open Task, or when a single Task is open and
+//! either the event comes from a subagent (`agent_id`) or nothing else can be
+//! running: no other tool was requested alongside the Task. Events that can't be
+//! placed stay under the turn, and the open Tasks are labelled
+//! `subagent_attribution = ambiguous` without counted totals.
+//!
+//! **Compaction spans.** `PreCompact` fires before Claude Code summarizes the
+//! conversation. The tracker holds the `compact.pre` event, which carries the
+//! context size of the last message before compaction, until the session's
+//! first event with a newer message, whose context size is the size after. It
+//! then emits a `compaction` span with `compact_tokens_before` and
+//! `compact_tokens_after`.
+//!
+//! Starts that never see an end (the tool was interrupted, the hook failed, or
+//! only one of the hooks is registered) are emitted as incomplete spans once they
+//! are older than the configured timeout, when their session ends, or when the
+//! agent stops. For turns the timeout counts from the session's last event.
+//! Events without a `tool_use_id` or session pass through unchanged.
+
+use crate::{context::CONTEXT_TOKENS, schema::TraceV1};
+use chrono::DateTime;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Cap on held starts. Beyond this the oldest are emitted as incomplete, so a
+/// stream of unmatched starts can't grow memory without bound.
+const MAX_PENDING: usize = 10_000;
+
+/// Sessions whose turn numbering and open subagents are remembered; the least
+/// recently active is forgotten first, since `session.end` often never arrives.
+const MAX_SESSIONS: usize = 1024;
+
+/// Files listed on a turn span; further files are left off.
+const MAX_TURN_FILES: usize = 500;
+
+/// Label recording whether a tool span saw both hooks.
+pub const SPAN_STATUS: &str = "span_status";
+
+/// Canonical event name of synthesized turn spans.
+pub const TURN_EVENT: &str = "turn";
+
+/// Canonical event name of synthesized compaction spans.
+pub const COMPACTION_EVENT: &str = "compaction";
+
+/// Label carrying the subagent kind (`Task` input `subagent_type`).
+pub const SUBAGENT_TYPE: &str = "subagent_type";
+
+struct Pending {
+    trace: TraceV1,
+    arrived: Instant,
+}
+
+/// Makes room for one more session in `map` once it holds [`MAX_SESSIONS`],
+/// forgetting the one whose `last_seen` is oldest.
+fn forget_oldest<V>(map: &mut HashMap<String, V>, last_seen: impl Fn(&V) -> Instant) {
+    while map.len() >= MAX_SESSIONS {
+        let Some(oldest) = map
+            .iter()
+            .min_by_key(|(_, v)| last_seen(v))
+            .map(|(k, _)| k.clone())
+        else {
+            break;
+        };
+        map.remove(&oldest);
+    }
+}
+
+/// Whether a tool runs a subagent.
+fn is_subagent_tool(name: &str) -> bool {
+    matches!(name, "Task" | "Agent")
+}
+
+/// Token usage per transcript message, so repeated enrichment with the same
+/// message isn't double-counted.
+#[derive(Default)]
+struct Usage(HashMap<String, (u32, u32, u32)>);
+
+impl Usage {
+    fn observe(&mut self, t: &TraceV1) {
+        let m = &t.metrics;
+        if !t.ids.message_id.is_empty() && m.total_tokens > 0 {
+            self.0.insert(
+                t.ids.message_id.clone(),
+                (m.prompt_tokens, m.completion_tokens, m.total_tokens),
+            );
+        }
+    }
+
+    /// Prompt, completion and total tokens.
+    fn totals(&self) -> (u32, u32, u32) {
+        self.0.values().fold((0, 0, 0), |a, u| {
+            (
+                a.0.saturating_add(u.0),
+                a.1.saturating_add(u.1),
+                a.2.saturating_add(u.2),
+            )
+        })
+    }
+}
+
+/// A subagent started by a held `Task` tool call.
+struct Subagent {
+    tool_use_id: String,
+    span_id: String,
+    kind: String,
+    usage: Usage,
+    tool_calls: u32,
+    /// The Task's message requested other tools, which run alongside it.
+    has_siblings: bool,
+    /// An event that may have been the subagent's couldn't be placed.
+    ambiguous: bool,
+    opened: Instant,
+}
+
+/// Index of the open subagent that `t` belongs to, if that can be told.
+fn owner(open: &[Subagent], t: &TraceV1) -> Option<usize> {
+    if let Some(id) = t.label("parent_tool_use_id") {
+        return open.iter().position(|s| s.tool_use_id == id);
+    }
+    if let Some(kind) = t.label("agent_type") {
+        let mut matches = open
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.kind == kind)
+            .map(|(i, _)| i);
+        if let (Some(i), None) = (matches.next(), matches.next()) {
+            return Some(i);
+        }
+    }
+    match open {
+        [only] if t.label("agent_id").is_some() || !only.has_siblings => Some(0),
+        _ => None,
+    }
+}
+
+/// Whether `t` could come from a running subagent. Subagents can't start
+/// subagents of their own.
+fn may_be_subagent_event(t: &TraceV1) -> bool {
+    match t.event.as_str() {
+        "tool.pre" | "tool.post" => !is_subagent_tool(&t.inputs.tool.name),
+        "subagent.stop" | "notification" => true,
+        _ => false,
+    }
+}
+
+/// An open user turn.
+struct Turn {
+    /// The `prompt.submit` event that opened the turn.
+    prompt: TraceV1,
+    /// Span ID assigned up front so children can point at it.
+    span_id: String,
+    index: u32,
+    tool_calls: u32,
+    model: String,
+    usage: Usage,
+    /// Files touched by the turn's tool calls, in first-seen order.
+    files: Vec<String>,
+    started: Instant,
+    last_seen: Instant,
+}
+
+impl Turn {
+    fn observe(&mut self, t: &TraceV1, now: Instant) {
+        self.last_seen = now;
+        for file in &t.inputs.files {
+            if self.files.len() < MAX_TURN_FILES && !self.files.contains(file) {
+                self.files.push(file.clone());
+            }
+        }
+        if !t.configuration.model.is_empty() {
+            self.model = t.configuration.model.clone();
+        }
+        self.usage.observe(t);
+    }
+
+    /// Builds the turn span. `end` is the closing `agent.stop` event, if any.
+    fn finish(self, end: Option<TraceV1>, now: Instant) -> TraceV1 {
+        let complete = end.is_some();
+        let mut span = end.unwrap_or_default();
+        let end_ts = std::mem::replace(&mut span.timestamp, self.prompt.timestamp.clone());
+        span.event = TURN_EVENT.to_string();
+        // The span's own metrics stay those of the Stop's message, so usage
+        // attribution treats it like any other event; turn totals go in labels.
+        let message_id = std::mem::take(&mut span.ids.message_id);
+        span.ids = self.prompt.ids.clone();
+        span.ids.message_id = message_id;
+        span.ids.span_id = self.span_id;
+        span.ids.parent_span_id.clear();
+        span.context = self.prompt.context.clone();
+        span.configuration.model = self.model;
+        span.inputs.files = self.files;
+
+        if complete {
+            set_duration(&mut span, &end_ts, self.started, now);
+        }
+        for label in self.prompt.labels {
+            if span.label(&label.key).is_none() {
+                span.labels.push(label);
+            }
+        }
+        span.set_label("turn_index", self.index.to_string());
+        span.set_label("tool_calls", self.tool_calls.to_string());
+        let (prompt, completion, total) = self.usage.totals();
+        span.set_label("turn_prompt_tokens", prompt.to_string());
+        span.set_label("turn_completion_tokens", completion.to_string());
+        span.set_label("turn_total_tokens", total.to_string());
+        span.set_label(
+            SPAN_STATUS,
+            if complete { "complete" } else { "incomplete" },
+        );
+        set_span_extension(&mut span, if complete { &end_ts } else { "" });
+        span
+    }
+}
+
+/// Open tool calls and turns, keyed by session.
+#[derive(Default)]
+pub struct SpanTracker {
+    /// Tool starts keyed by session and `tool_use_id`.
+    pending: HashMap<(String, String), Pending>,
+    /// Open turn per session.
+    turns: HashMap<String, Turn>,
+    /// Turns started per session, for `turn_index`.
+    turn_counts: HashMap<String, (u32, Instant)>,
+    /// Open subagents per session, innermost last.
+    subagents: HashMap<String, Vec<Subagent>>,
+    /// `compact.pre` events waiting for the compacted context size, by session.
+    compactions: HashMap<String, Pending>,
+}
+
+impl SpanTracker {
+    /// Number of tool starts currently held.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether nothing is held, so expiry can be skipped.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.turns.is_empty() && self.compactions.is_empty()
+    }
+
+    /// Feeds one mapped event and returns the events to emit now (zero or more).
+    pub fn process(&mut self, mut t: TraceV1, now: Instant) -> Vec<TraceV1> {
+        let session = t.ids.session_id.clone();
+        let mut out = Vec::new();
+
+        // The first message after a compaction reports the compacted size.
+        if let Some(held) = self.compactions.get(&session)
+            && t.label(CONTEXT_TOKENS).is_some()
+            && t.ids.message_id != held.trace.ids.message_id
+            && let Some(held) = self.compactions.remove(&session)
+        {
+            out.push(finish_compaction(held, Some(&t), now));
+        }
+
+        match t.event.as_str() {
+            "prompt.submit" if !session.is_empty() => {
+                // A prompt while a turn is open means the previous Stop was missed.
+                if let Some(open) = self.turns.remove(&session) {
+                    out.push(open.finish(None, now));
+                }
+                if !self.turn_counts.contains_key(&session) {
+                    forget_oldest(&mut self.turn_counts, |(_, seen)| *seen);
+                }
+                let (index, seen) = self.turn_counts.entry(session.clone()).or_insert((0, now));
+                *index += 1;
+                *seen = now;
+                let turn = Turn {
+                    span_id: uuid::Uuid::new_v4().to_string(),
+                    index: *index,
+                    tool_calls: 0,
+                    model: String::new(),
+                    usage: Usage::default(),
+                    files: Vec::new(),
+                    started: now,
+                    last_seen: now,
+                    prompt: t,
+                };
+                self.turns.insert(session, turn);
+                return out;
+            }
+            "agent.stop" => {
+                if let Some(mut turn) = self.turns.remove(&session) {
+                    turn.observe(&t, now);
+                    out.push(turn.finish(Some(t), now));
+                    return out;
+                }
+            }
+            "session.end" => {
+                out.extend(self.take(|k, _| k.0 == session));
+                if let Some(held) = self.compactions.remove(&session) {
+                    out.push(finish_compaction(held, None, now));
+                }
+                if let Some(turn) = self.turns.remove(&session) {
+                    out.push(turn.finish(None, now));
+                }
+                self.turn_counts.remove(&session);
+                self.subagents.remove(&session);
+                out.push(t);
+                return out;
+            }
+            _ => {}
+        }
+
+        let key = t
+            .label("tool_use_id")
+            .map(|id| (session.clone(), id.to_string()));
+        let new_call = match t.event.as_str() {
+            "tool.pre" => true,
+            "tool.post" => !key.as_ref().is_some_and(|k| self.pending.contains_key(k)),
+            _ => false,
+        };
+
+        // A Task's own end closes its subagent before attribution.
+        let finished = match (&key, t.event.as_str()) {
+            (Some(key), "tool.post") => self.close_subagent(key),
+            _ => None,
+        };
+        self.prune_subagents(&session);
+        if let Some(open) = self.subagents.get_mut(&session)
+            && may_be_subagent_event(&t)
+        {
+            match owner(open, &t) {
+                Some(i) => {
+                    let sub = &mut open[i];
+                    sub.usage.observe(&t);
+                    if new_call {
+                        sub.tool_calls += 1;
+                    }
+                    if t.ids.parent_span_id.is_empty() {
+                        t.ids.parent_span_id = sub.span_id.clone();
+                    }
+                    if t.label(SUBAGENT_TYPE).is_none() {
+                        t.set_label(SUBAGENT_TYPE, sub.kind.clone());
+                    }
+                    t.set_label("parent_tool_use_id", sub.tool_use_id.clone());
+                }
+                None => open.iter_mut().for_each(|s| s.ambiguous = true),
+            }
+        }
+        if let Some(turn) = self.turns.get_mut(&session) {
+            turn.observe(&t, now);
+            if t.ids.parent_span_id.is_empty() {
+                t.ids.parent_span_id = turn.span_id.clone();
+            }
+            if new_call {
+                turn.tool_calls += 1;
+            }
+        }
+        if let Some(sub) = finished {
+            // Totals reported by the Task result are exact either way.
+            if sub.ambiguous {
+                t.set_label("subagent_attribution", "ambiguous");
+            } else {
+                let (_, _, total) = sub.usage.totals();
+                if t.label("subagent_total_tokens").is_none() {
+                    t.set_label("subagent_total_tokens", total.to_string());
+                }
+                if t.label("subagent_tool_calls").is_none() {
+                    t.set_label("subagent_tool_calls", sub.tool_calls.to_string());
+                }
+            }
+        }
+        if let Some((_, id)) = &key
+            && t.event == "tool.pre"
+            && is_subagent_tool(&t.inputs.tool.name)
+        {
+            // Fix the Task's span ID now so the subagent's events can point at it.
+            if t.ids.span_id.is_empty() {
+                t.ids.span_id = uuid::Uuid::new_v4().to_string();
+            }
+            let kind = t.label(SUBAGENT_TYPE).unwrap_or_default().to_string();
+            if !self.subagents.contains_key(&session) {
+                self.prune_all_subagents();
+                forget_oldest(&mut self.subagents, |open| {
+                    open.iter().map(|s| s.opened).max().unwrap_or(now)
+                });
+            }
+            self.subagents
+                .entry(session.clone())
+                .or_default()
+                .push(Subagent {
+                    tool_use_id: id.clone(),
+                    span_id: t.ids.span_id.clone(),
+                    kind,
+                    usage: Usage::default(),
+                    tool_calls: 0,
+                    has_siblings: t.outputs.tool_calls.len() > 1,
+                    ambiguous: false,
+                    opened: now,
+                });
+        }
+
+        match (t.event.as_str(), key) {
+            ("tool.pre", Some(key)) => {
+                if self.pending.len() >= MAX_PENDING {
+                    out.extend(self.evict_oldest());
+                }
+                // A repeated start replaces the earlier one; emit that as incomplete.
+                let old = self.pending.insert(
+                    key,
+                    Pending {
+                        trace: t,
+                        arrived: now,
+                    },
+                );
+                out.extend(old.map(|p| incomplete(p.trace)));
+            }
+            ("tool.post", Some(key)) => match self.pending.remove(&key) {
+                Some(start) => out.push(complete(start, t, now)),
+                None => out.push(t),
+            },
+            ("compact.pre", _) if !session.is_empty() => {
+                let held = Pending {
+                    trace: t,
+                    arrived: now,
+                };
+                // A second compaction before any new message: the first never finished.
+                if let Some(old) = self.compactions.insert(session, held) {
+                    out.push(finish_compaction(old, None, now));
+                }
+            }
+            _ => out.push(t),
+        }
+        out
+    }
+
+    /// Emits tool starts older than `max_age`, and turns whose session has been
+    /// quiet that long, as incomplete spans.
+    pub fn expire(&mut self, now: Instant, max_age: Duration) -> Vec<TraceV1> {
+        let mut out = self.take(|_, p| now.saturating_duration_since(p.arrived) >= max_age);
+        self.prune_all_subagents();
+        let idle: Vec<_> = self
+            .turns
+            .iter()
+            .filter(|(_, turn)| now.saturating_duration_since(turn.last_seen) >= max_age)
+            .map(|(s, _)| s.clone())
+            .collect();
+        for session in idle {
+            if let Some(turn) = self.turns.remove(&session) {
+                out.push(turn.finish(None, now));
+            }
+        }
+        let stale: Vec<_> = self
+            .compactions
+            .iter()
+            .filter(|(_, p)| now.saturating_duration_since(p.arrived) >= max_age)
+            .map(|(s, _)| s.clone())
+            .collect();
+        for session in stale {
+            if let Some(held) = self.compactions.remove(&session) {
+                out.push(finish_compaction(held, None, now));
+            }
+        }
+        out
+    }
+
+    /// Emits everything held as incomplete, e.g. when the agent stops.
+    pub fn drain(&mut self) -> Vec<TraceV1> {
+        self.subagents.clear();
+        let mut out = self.take(|_, _| true);
+        out.extend(
+            self.turns
+                .drain()
+                .map(|(_, turn)| turn.finish(None, Instant::now())),
+        );
+        out.extend(
+            self.compactions
+                .drain()
+                .map(|(_, held)| finish_compaction(held, None, Instant::now())),
+        );
+        out
+    }
+
+    /// Removes and returns the subagent started by the given Task call.
+    fn close_subagent(&mut self, key: &(String, String)) -> Option<Subagent> {
+        let open = self.subagents.get_mut(&key.0)?;
+        let pos = open.iter().position(|s| s.tool_use_id == key.1)?;
+        let sub = open.remove(pos);
+        if open.is_empty() {
+            self.subagents.remove(&key.0);
+        }
+        Some(sub)
+    }
+
+    /// [`SpanTracker::prune_subagents`] for every session.
+    fn prune_all_subagents(&mut self) {
+        let sessions: Vec<_> = self.subagents.keys().cloned().collect();
+        for session in sessions {
+            self.prune_subagents(&session);
+        }
+    }
+
+    /// Forgets subagents whose Task start was already emitted as incomplete.
+    fn prune_subagents(&mut self, session: &str) {
+        if let Some(open) = self.subagents.get_mut(session) {
+            open.retain(|s| {
+                self.pending
+                    .contains_key(&(session.to_string(), s.tool_use_id.clone()))
+            });
+            if open.is_empty() {
+                self.subagents.remove(session);
+            }
+        }
+    }
+
+    fn take(&mut self, pred: impl Fn(&(String, String), &Pending) -> bool) -> Vec<TraceV1> {
+        let keys: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(k, p)| pred(k, p))
+            .map(|(k, _)| k.clone())
+            .collect();
+        let mut taken: Vec<_> = keys
+            .into_iter()
+            .filter_map(|k| self.pending.remove(&k))
+            .collect();
+        taken.sort_by_key(|p| p.arrived);
+        taken.into_iter().map(|p| incomplete(p.trace)).collect()
+    }
+
+    fn evict_oldest(&mut self) -> Option<TraceV1> {
+        let key = self
+            .pending
+            .iter()
+            .min_by_key(|(_, p)| p.arrived)
+            .map(|(k, _)| k.clone())?;
+        self.pending.remove(&key).map(|p| incomplete(p.trace))
+    }
+}
+
+/// Builds the compaction span from the held `compact.pre` event and the first
+/// event after it that reports a new message's context size, if one arrived.
+fn finish_compaction(start: Pending, next: Option<&TraceV1>, now: Instant) -> TraceV1 {
+    let mut span = start.trace;
+    span.event = COMPACTION_EVENT.to_string();
+    if let Some(before) = span.label(CONTEXT_TOKENS).map(str::to_string) {
+        span.set_label("compact_tokens_before", before);
+    }
+    let Some(next) = next else {
+        return incomplete(span);
+    };
+    set_duration(&mut span, &next.timestamp, start.arrived, now);
+    let after = next.label(CONTEXT_TOKENS).unwrap_or_default().to_string();
+    span.set_label("compact_tokens_after", after);
+    span.set_label(SPAN_STATUS, "complete");
+    set_span_extension(&mut span, &next.timestamp);
+    span
+}
+
+/// Merges a start and its end into one span.
+fn complete(start: Pending, end: TraceV1, now: Instant) -> TraceV1 {
+    let mut span = end;
+    let end_ts = std::mem::replace(&mut span.timestamp, start.trace.timestamp.clone());
+    set_duration(&mut span, &end_ts, start.arrived, now);
+
+    if span.inputs.tool.args.is_null() {
+        span.inputs.tool.args = start.trace.inputs.tool.args;
+    }
+    if span.inputs.tool.name.is_empty() {
+        span.inputs.tool.name = start.trace.inputs.tool.name;
+    }
+    if span.ids.span_id.is_empty() {
+        span.ids.span_id = start.trace.ids.span_id;
+    }
+    if span.ids.parent_span_id.is_empty() {
+        span.ids.parent_span_id = start.trace.ids.parent_span_id;
+    }
+    for label in start.trace.labels {
+        if span.label(&label.key).is_none() {
+            span.labels.push(label);
+        }
+    }
+    span.set_label(SPAN_STATUS, "complete");
+    set_span_extension(&mut span, &end_ts);
+    span
+}
+
+/// Sets `latency_ms.total` from the span's start timestamp to `end_ts`, falling
+/// back to arrival times at the agent when either timestamp doesn't parse.
+fn set_duration(span: &mut TraceV1, end_ts: &str, arrived: Instant, now: Instant) {
+    match (
+        DateTime::parse_from_rfc3339(&span.timestamp),
+        DateTime::parse_from_rfc3339(end_ts),
+    ) {
+        (Ok(s), Ok(e)) => {
+            let ms = (e - s).num_milliseconds().max(0) as u64;
+            span.metrics.latency_ms.total = ms.min(u64::from(u32::MAX)) as u32;
+            span.metrics.latency_estimated = false;
+        }
+        _ => {
+            let ms = now.saturating_duration_since(arrived).as_millis();
+            span.metrics.latency_ms.total = ms.min(u128::from(u32::MAX)) as u32;
+            span.metrics.latency_estimated = true;
+        }
+    }
+}
+
+/// Marks a start that never saw its end.
+fn incomplete(mut t: TraceV1) -> TraceV1 {
+    t.set_label(SPAN_STATUS, "incomplete");
+    set_span_extension(&mut t, "");
+    t
+}
+
+fn set_span_extension(t: &mut TraceV1, end: &str) {
+    if !t.extensions.is_object() {
+        t.extensions = serde_json::json!({});
+    }
+    let span = serde_json::json!({
+        "start": t.timestamp,
+        "end": if end.is_empty() { None } else { Some(end) },
+    });
+    if let Some(obj) = t.extensions.as_object_mut() {
+        obj.insert("span".to_string(), span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool_event(event: &str, session: &str, id: &str, ts: &str) -> TraceV1 {
+        let mut t = TraceV1 {
+            event: event.to_string(),
+            timestamp: ts.to_string(),
+            ..Default::default()
+        };
+        t.ids.session_id = session.to_string();
+        t.set_label("tool_use_id", id);
+        t.inputs.tool.name = "Bash".to_string();
+        t
+    }
+
+    #[test]
+    fn test_pre_and_post_become_one_timed_span() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+
+        let mut pre = tool_event("tool.pre", "s", "t1", "2025-11-13T10:30:00.000Z");
+        pre.inputs.tool.args = serde_json::json!({ "command": "ls" });
+        assert!(tracker.process(pre, now).is_empty(), "start is held");
+        assert_eq!(tracker.len(), 1);
+
+        let mut post = tool_event("tool.post", "s", "t1", "2025-11-13T10:30:01.250Z");
+        post.outputs.assistant_text = "file.txt".to_string();
+        let out = tracker.process(post, now);
+
+        assert_eq!(out.len(), 1);
+        let span = &out[0];
+        assert_eq!(span.event, "tool.post");
+        assert_eq!(span.timestamp, "2025-11-13T10:30:00.000Z", "starts at pre");
+        assert_eq!(span.metrics.latency_ms.total, 1250);
+        assert!(!span.metrics.latency_estimated);
+        assert_eq!(span.inputs.tool.args["command"], "ls");
+        assert_eq!(span.outputs.assistant_text, "file.txt");
+        assert_eq!(span.label(SPAN_STATUS), Some("complete"));
+        assert_eq!(span.extensions["span"]["end"], "2025-11-13T10:30:01.250Z");
+        assert_eq!(tracker.len(), 0);
+    }
+
+    #[test]
+    fn test_same_tool_use_id_in_other_session_does_not_match() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        tracker.process(tool_event("tool.pre", "a", "t1", ""), now);
+
+        let out = tracker.process(tool_event("tool.post", "b", "t1", ""), now);
+        assert_eq!(
+            out[0].label(SPAN_STATUS),
+            None,
+            "unmatched post passes through"
+        );
+        assert_eq!(tracker.len(), 1);
+    }
+
+    #[test]
+    fn test_unmatched_starts_expire_as_incomplete() {
+        let mut tracker = SpanTracker::default();
+        let start = Instant::now();
+        tracker.process(tool_event("tool.pre", "s", "old", ""), start);
+        tracker.process(
+            tool_event("tool.pre", "s", "new", ""),
+            start + Duration::from_secs(50),
+        );
+
+        let later = start + Duration::from_secs(60);
+        let expired = tracker.expire(later, Duration::from_secs(30));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].label("tool_use_id"), Some("old"));
+        assert_eq!(expired[0].label(SPAN_STATUS), Some("incomplete"));
+        assert_eq!(tracker.len(), 1);
+
+        assert_eq!(tracker.drain().len(), 1);
+        assert_eq!(tracker.len(), 0);
+    }
+
+    #[test]
+    fn test_session_end_flushes_that_session() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        tracker.process(tool_event("tool.pre", "a", "t1", ""), now);
+        tracker.process(tool_event("tool.pre", "b", "t2", ""), now);
+
+        let mut end = TraceV1 {
+            event: "session.end".to_string(),
+            ..Default::default()
+        };
+        end.ids.session_id = "a".to_string();
+        let out = tracker.process(end, now);
+
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].label(SPAN_STATUS), Some("incomplete"));
+        assert_eq!(out[1].event, "session.end");
+        assert_eq!(tracker.len(), 1, "other session still pending");
+    }
+
+    #[test]
+    fn test_events_without_tool_use_id_pass_through() {
+        let mut tracker = SpanTracker::default();
+        let pre = TraceV1 {
+            event: "tool.pre".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(tracker.process(pre, Instant::now()).len(), 1);
+        assert_eq!(tracker.len(), 0);
+    }
+
+    fn session_event(event: &str, session: &str, ts: &str) -> TraceV1 {
+        let mut t = TraceV1 {
+            event: event.to_string(),
+            timestamp: ts.to_string(),
+            ..Default::default()
+        };
+        t.ids.session_id = session.to_string();
+        t
+    }
+
+    #[test]
+    fn test_turn_spans_prompt_to_stop_and_parents_tools() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+
+        let prompt = session_event("prompt.submit", "s", "2025-11-13T10:30:00.000Z");
+        assert!(
+            tracker.process(prompt, now).is_empty(),
+            "prompt opens a turn"
+        );
+
+        tracker.process(tool_event("tool.pre", "s", "t1", ""), now);
+        let tool = tracker.process(tool_event("tool.post", "s", "t1", ""), now);
+        let unmatched = tracker.process(tool_event("tool.post", "s", "t2", ""), now);
+
+        let mut stop = session_event("agent.stop", "s", "2025-11-13T10:30:04.500Z");
+        stop.configuration.model = "claude-sonnet-4".to_string();
+        stop.ids.message_id = "msg-1".to_string();
+        stop.metrics.prompt_tokens = 100;
+        stop.metrics.completion_tokens = 20;
+        stop.metrics.total_tokens = 120;
+        let out = tracker.process(stop, now);
+
+        assert_eq!(out.len(), 1);
+        let turn = &out[0];
+        assert_eq!(turn.event, TURN_EVENT);
+        assert_eq!(turn.timestamp, "2025-11-13T10:30:00.000Z");
+        assert_eq!(turn.metrics.latency_ms.total, 4500);
+        assert_eq!(turn.metrics.total_tokens, 120, "the Stop's own message");
+        assert_eq!(turn.label("turn_total_tokens"), Some("120"));
+        assert_eq!(turn.configuration.model, "claude-sonnet-4");
+        assert_eq!(turn.label("tool_calls"), Some("2"));
+        assert_eq!(turn.label("turn_index"), Some("1"));
+        assert_eq!(turn.label(SPAN_STATUS), Some("complete"));
+        assert!(!turn.ids.span_id.is_empty());
+        assert_eq!(tool[0].ids.parent_span_id, turn.ids.span_id);
+        assert_eq!(unmatched[0].ids.parent_span_id, turn.ids.span_id);
+        assert!(tracker.is_idle());
+    }
+
+    fn with_context(mut t: TraceV1, message: &str, tokens: u32) -> TraceV1 {
+        t.ids.message_id = message.to_string();
+        t.set_label(CONTEXT_TOKENS, tokens.to_string());
+        t
+    }
+
+    #[test]
+    fn test_compaction_span_reports_before_and_after() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        let pre = with_context(
+            session_event("compact.pre", "s", "2025-11-13T10:30:00.000Z"),
+            "m1",
+            180_000,
+        );
+        assert!(tracker.process(pre, now).is_empty(), "held");
+
+        // Still the pre-compaction message: keeps waiting.
+        let same = with_context(session_event("session.start", "s", ""), "m1", 180_000);
+        assert_eq!(tracker.process(same, now).len(), 1);
+        assert!(!tracker.is_idle());
+
+        let next = with_context(
+            tool_event("tool.post", "s", "t1", "2025-11-13T10:30:42.000Z"),
+            "m2",
+            24_000,
+        );
+        let out = tracker.process(next, now);
+        assert_eq!(out.len(), 2);
+        let span = &out[0];
+        assert_eq!(span.event, COMPACTION_EVENT);
+        assert_eq!(span.label("compact_tokens_before"), Some("180000"));
+        assert_eq!(span.label("compact_tokens_after"), Some("24000"));
+        assert_eq!(span.label(SPAN_STATUS), Some("complete"));
+        assert_eq!(span.metrics.latency_ms.total, 42_000);
+        assert_eq!(out[1].event, "tool.post");
+        assert!(tracker.is_idle());
+    }
+
+    #[test]
+    fn test_unfinished_compaction_is_incomplete() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        let pre = with_context(session_event("compact.pre", "s", ""), "m1", 150_000);
+        tracker.process(pre, now);
+
+        let out = tracker.process(session_event("session.end", "s", ""), now);
+        assert_eq!(out[0].event, COMPACTION_EVENT);
+        assert_eq!(out[0].label(SPAN_STATUS), Some("incomplete"));
+        assert_eq!(out[0].label("compact_tokens_before"), Some("150000"));
+        assert_eq!(out[0].label("compact_tokens_after"), None);
+        assert_eq!(out[1].event, "session.end");
+    }
+
+    #[test]
+    fn test_turn_lists_files_of_its_tools() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        tracker.process(session_event("prompt.submit", "s", ""), now);
+        for (id, file) in [("t1", "src/a.rs"), ("t2", "src/b.rs"), ("t3", "src/a.rs")] {
+            let mut post = tool_event("tool.post", "s", id, "");
+            post.inputs.files = vec![file.to_string()];
+            tracker.process(post, now);
+        }
+        let out = tracker.process(session_event("agent.stop", "s", ""), now);
+        assert_eq!(out[0].inputs.files, ["src/a.rs", "src/b.rs"]);
+    }
+
+    #[test]
+    fn test_turn_tokens_count_each_message_once() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        tracker.process(session_event("prompt.submit", "s", ""), now);
+
+        for (msg, tokens) in [("m1", 10), ("m1", 10), ("m2", 5)] {
+            let mut t = tool_event("tool.post", "s", msg, "");
+            t.ids.message_id = msg.to_string();
+            t.metrics.total_tokens = tokens;
+            tracker.process(t, now);
+        }
+        let out = tracker.process(session_event("agent.stop", "s", ""), now);
+        assert_eq!(out[0].label("turn_total_tokens"), Some("15"));
+        assert_eq!(
+            out[0].metrics.total_tokens, 0,
+            "Stop had no usage of its own"
+        );
+    }
+
+    #[test]
+    fn test_unfinished_turns_are_incomplete() {
+        let mut tracker = SpanTracker::default();
+        let start = Instant::now();
+        tracker.process(session_event("prompt.submit", "s", ""), start);
+
+        // A second prompt closes the first turn.
+        let out = tracker.process(session_event("prompt.submit", "s", ""), start);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].label(SPAN_STATUS), Some("incomplete"));
+        assert_eq!(out[0].label("turn_index"), Some("1"));
+
+        // Activity keeps the turn open past the timeout.
+        tracker.process(
+            tool_event("tool.post", "s", "t1", ""),
+            start + Duration::from_secs(50),
+        );
+        let max_age = Duration::from_secs(30);
+        assert!(
+            tracker
+                .expire(start + Duration::from_secs(60), max_age)
+                .is_empty()
+        );
+        let out = tracker.expire(start + Duration::from_secs(90), max_age);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].label("turn_index"), Some("2"));
+        assert!(tracker.is_idle());
+
+        // A stop with no open turn passes through unchanged.
+        let out = tracker.process(session_event("agent.stop", "s", ""), start);
+        assert_eq!(out[0].event, "agent.stop");
+    }
+
+    #[test]
+    fn test_subagent_events_nest_under_task_span() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        tracker.process(session_event("prompt.submit", "s", ""), now);
+
+        let mut task = tool_event("tool.pre", "s", "task1", "");
+        task.inputs.tool.name = "Task".to_string();
+        task.set_label(SUBAGENT_TYPE, "Explore");
+        assert!(tracker.process(task.clone(), now).is_empty());
+
+        let mut child = tool_event("tool.post", "s", "c1", "");
+        child.ids.message_id = "sub-msg".to_string();
+        child.metrics.total_tokens = 40;
+        let child = tracker.process(child, now).remove(0);
+        let stop = tracker
+            .process(session_event("subagent.stop", "s", ""), now)
+            .remove(0);
+
+        task.event = "tool.post".to_string();
+        let task = tracker.process(task, now).remove(0);
+        assert!(!task.ids.span_id.is_empty(), "span ID fixed at the start");
+        assert_eq!(child.ids.parent_span_id, task.ids.span_id);
+        assert_eq!(stop.ids.parent_span_id, task.ids.span_id);
+        assert_eq!(child.label(SUBAGENT_TYPE), Some("Explore"));
+        assert_eq!(child.label("parent_tool_use_id"), Some("task1"));
+        assert_eq!(task.label("subagent_total_tokens"), Some("40"));
+        assert_eq!(task.label("subagent_tool_calls"), Some("1"));
+        assert_eq!(
+            task.label("parent_tool_use_id"),
+            None,
+            "Task is not its own child"
+        );
+
+        // After the Task ends, events go back under the turn.
+        let after = tracker
+            .process(tool_event("tool.post", "s", "c2", ""), now)
+            .remove(0);
+        assert_eq!(after.ids.parent_span_id, task.ids.parent_span_id);
+        assert_eq!(after.label(SUBAGENT_TYPE), None);
+
+        let turn = tracker
+            .process(session_event("agent.stop", "s", ""), now)
+            .remove(0);
+        assert_eq!(turn.ids.span_id, task.ids.parent_span_id);
+        assert_eq!(
+            turn.label("turn_total_tokens"),
+            Some("40"),
+            "subagent tokens count toward the turn"
+        );
+    }
+
+    /// A Task start whose message requested `calls` tool calls.
+    fn task_event(id: &str, kind: &str, calls: usize) -> TraceV1 {
+        let mut task = tool_event("tool.pre", "s", id, "");
+        task.inputs.tool.name = "Task".to_string();
+        task.set_label(SUBAGENT_TYPE, kind);
+        task.outputs.tool_calls = vec![Default::default(); calls];
+        task
+    }
+
+    #[test]
+    fn test_concurrent_subagents_are_told_apart_or_marked_ambiguous() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        tracker.process(session_event("prompt.submit", "s", ""), now);
+        let explore = task_event("task1", "Explore", 2);
+        let plan = task_event("task2", "Plan", 2);
+        tracker.process(explore.clone(), now);
+        tracker.process(plan.clone(), now);
+
+        let mut by_type = tool_event("tool.post", "s", "c1", "");
+        by_type.set_label("agent_type", "Plan");
+        let by_type = tracker.process(by_type, now).remove(0);
+        assert_eq!(by_type.label("parent_tool_use_id"), Some("task2"));
+
+        let mut linked = tool_event("tool.post", "s", "c2", "");
+        linked.set_label("parent_tool_use_id", "task1");
+        let linked = tracker.process(linked, now).remove(0);
+        assert_eq!(linked.label(SUBAGENT_TYPE), Some("Explore"));
+
+        let unknown = tracker
+            .process(tool_event("tool.post", "s", "c3", ""), now)
+            .remove(0);
+        assert_eq!(unknown.label(SUBAGENT_TYPE), None);
+        assert_eq!(unknown.label("parent_tool_use_id"), None);
+
+        for mut task in [explore, plan] {
+            task.event = "tool.post".to_string();
+            let task = tracker.process(task, now).remove(0);
+            assert_eq!(task.label("subagent_attribution"), Some("ambiguous"));
+            assert_eq!(task.label("subagent_total_tokens"), None);
+            assert_eq!(task.label("subagent_tool_calls"), None);
+        }
+    }
+
+    #[test]
+    fn test_tools_alongside_a_task_stay_with_the_main_agent() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        let task = task_event("task1", "Explore", 2);
+        let task_span = tracker.process(task.clone(), now);
+        assert!(task_span.is_empty());
+
+        let sibling = tracker
+            .process(tool_event("tool.post", "s", "read1", ""), now)
+            .remove(0);
+        assert_eq!(sibling.label("parent_tool_use_id"), None);
+
+        let mut child = tool_event("tool.post", "s", "c1", "");
+        child.set_label("agent_id", "a1");
+        let child = tracker.process(child, now).remove(0);
+        assert_eq!(child.label("parent_tool_use_id"), Some("task1"));
+
+        let mut end = task;
+        end.event = "tool.post".to_string();
+        let end = tracker.process(end, now).remove(0);
+        assert_eq!(end.label("subagent_attribution"), Some("ambiguous"));
+    }
+
+    #[test]
+    fn test_per_session_state_is_bounded() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        for i in 0..MAX_SESSIONS + 10 {
+            let session = format!("s{i}");
+            let at = now + Duration::from_millis(i as u64);
+            tracker.process(session_event("prompt.submit", &session, ""), at);
+            tracker.process(session_event("agent.stop", &session, ""), at);
+            let mut task = tool_event("tool.pre", &session, "task", "");
+            task.inputs.tool.name = "Task".to_string();
+            tracker.process(task, at);
+        }
+        assert_eq!(tracker.turn_counts.len(), MAX_SESSIONS);
+        assert!(!tracker.turn_counts.contains_key("s0"), "oldest forgotten");
+        assert!(tracker.subagents.len() <= MAX_SESSIONS);
+
+        // Once their Task starts expire, idle subagents are forgotten too.
+        tracker.expire(now + Duration::from_secs(60), Duration::from_secs(1));
+        assert!(tracker.subagents.is_empty());
+    }
+
+    #[test]
+    fn test_reported_subagent_totals_are_kept() {
+        let mut tracker = SpanTracker::default();
+        let now = Instant::now();
+        let mut task = tool_event("tool.pre", "s", "task1", "");
+        task.inputs.tool.name = "Task".to_string();
+        tracker.process(task.clone(), now);
+
+        task.event = "tool.post".to_string();
+        task.set_label("subagent_total_tokens", "1234");
+        let out = tracker.process(task, now);
+        assert_eq!(out[0].label("subagent_total_tokens"), Some("1234"));
+        assert_eq!(out[0].label("subagent_tool_calls"), Some("0"));
+    }
+}
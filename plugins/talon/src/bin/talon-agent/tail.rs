@@ -0,0 +1,316 @@
+//! Live event tail for debugging hook wiring.
+//!
+//! `talon-agent tail` subscribes over the control socket. For every frame the
+//! batching loop processes, the agent publishes a [`TailFrame`] with the raw tap
+//! frame, the mapped TraceV1 and the outgoing Beak payload (or the quarantine
+//! reason if mapping failed) to every subscriber whose [`TailFilter`] matches.
+//!
+//! Publishing never blocks the pipeline: each subscriber has a bounded queue and
+//! frames are dropped for subscribers that fall behind.
+
+use crate::beak_adapter::{BeakOptions, to_beak_format};
+use crate::schema::TraceV1;
+use crossbeam_channel as chan;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use std::sync::{
+    Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+/// Frames buffered per subscriber before new frames are dropped.
+const SUBSCRIBER_QUEUE: usize = 1024;
+
+/// Subscription filter. Empty fields match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TailFilter {
+    /// Event type, either as the hook name (`PostToolUse`) or canonical (`tool.post`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+
+    /// Claude Code session identifier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+
+    /// Tool name (e.g. `Bash`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+}
+
+impl TailFilter {
+    /// Checks a frame against the filter, looking at both the raw tap frame and the
+    /// mapped trace so filters work even for frames that failed to map.
+    pub fn matches(&self, raw: &Json, trace: Option<&TraceV1>) -> bool {
+        let payload = raw.get("payload");
+
+        let event_ok = self.event.as_deref().is_none_or(|want| {
+            str_at(raw.get("event")) == want || trace.is_some_and(|t| t.event == want)
+        });
+        let session_ok = self.session_id.as_deref().is_none_or(|want| {
+            str_at(raw.get("env").and_then(|e| e.get("session_id"))) == want
+                || str_at(payload.and_then(|p| p.get("session_id"))) == want
+                || trace.is_some_and(|t| t.ids.session_id == want)
+        });
+        let tool_ok = self.tool.as_deref().is_none_or(|want| {
+            str_at(payload.and_then(|p| p.get("tool_name"))) == want
+                || trace.is_some_and(|t| t.inputs.tool.name == want)
+        });
+
+        event_ok && session_ok && tool_ok
+    }
+}
+
+fn str_at(v: Option<&Json>) -> &str {
+    v.and_then(|x| x.as_str()).unwrap_or("")
+}
+
+/// One processed frame as seen by tail subscribers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TailFrame {
+    /// Frame as received from talon-tap (or raw text if it was not JSON).
+    pub raw: Json,
+
+    /// Canonical TraceV1 after mapping and canonicalization.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Json>,
+
+    /// Payload as it will be sent to the collector.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outgoing: Option<Json>,
+
+    /// Quarantine reason when the frame could not be mapped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// Why the frame produced no outgoing event yet (e.g. a held tool start).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+struct Subscriber {
+    filter: TailFilter,
+    tx: chan::Sender<TailFrame>,
+}
+
+/// Registry of live tail subscribers.
+#[derive(Default)]
+pub struct TailHub {
+    subscribers: Mutex<Vec<Subscriber>>,
+    /// Mirrors `subscribers.len()` so the hot path can skip work without locking.
+    active: AtomicUsize,
+}
+
+impl TailHub {
+    /// Registers a subscriber and returns the receiving end of its queue.
+    pub fn subscribe(&self, filter: TailFilter) -> chan::Receiver<TailFrame> {
+        let (tx, rx) = chan::bounded(SUBSCRIBER_QUEUE);
+        let mut subs = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subs.push(Subscriber { filter, tx });
+        self.active.store(subs.len(), Ordering::Relaxed);
+        rx
+    }
+
+    /// Whether anyone is listening. Callers use this to skip building frames.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed) > 0
+    }
+
+    /// Publishes a successfully mapped frame.
+    pub fn publish_trace(&self, raw: &Json, trace: &TraceV1, beak: &BeakOptions) {
+        self.publish(raw, Some(trace), || TailFrame {
+            raw: raw.clone(),
+            trace: serde_json::to_value(trace).ok(),
+            outgoing: serde_json::to_value(to_beak_format(trace, beak)).ok(),
+            error: None,
+            note: None,
+        });
+    }
+
+    /// Publishes a frame the pipeline is holding back, such as a tool start
+    /// waiting for its end or a prompt that opens a turn.
+    pub fn publish_held(&self, raw: &Json, trace: &TraceV1) {
+        let note = match trace.event.as_str() {
+            "prompt.submit" => "held until the turn ends (agent.stop)",
+            "compact.pre" => "held until a new message reports the compacted context size",
+            _ => "held until the matching tool.post arrives",
+        };
+        self.publish(raw, Some(trace), || TailFrame {
+            raw: raw.clone(),
+            trace: serde_json::to_value(trace).ok(),
+            outgoing: None,
+            error: None,
+            note: Some(note.to_string()),
+        });
+    }
+
+    /// Publishes a frame that `processors.drop_events` keeps from being sent.
+    pub fn publish_dropped(&self, raw: &Json, trace: &TraceV1) {
+        self.publish(raw, Some(trace), || TailFrame {
+            raw: raw.clone(),
+            trace: serde_json::to_value(trace).ok(),
+            outgoing: None,
+            error: None,
+            note: Some("dropped by processors.drop_events".to_string()),
+        });
+    }
+
+    /// Publishes a frame that was quarantined.
+    pub fn publish_error(&self, raw: &Json, reason: &str) {
+        self.publish(raw, None, || TailFrame {
+            raw: raw.clone(),
+            trace: None,
+            outgoing: None,
+            error: Some(reason.to_string()),
+            note: None,
+        });
+    }
+
+    fn publish(&self, raw: &Json, trace: Option<&TraceV1>, build: impl FnOnce() -> TailFrame) {
+        if !self.is_active() {
+            return;
+        }
+        let mut subs = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        let mut frame = None;
+        let mut build = Some(build);
+        subs.retain(|sub| {
+            if !sub.filter.matches(raw, trace) {
+                return true;
+            }
+            let frame = frame.get_or_insert_with(|| (build.take().expect("built once"))());
+            // Full queue: drop this frame for the slow subscriber but keep it registered.
+            !matches!(
+                sub.tx.try_send(frame.clone()),
+                Err(chan::TrySendError::Disconnected(_))
+            )
+        });
+        self.active.store(subs.len(), Ordering::Relaxed);
+    }
+}
+
+/// Renders a frame for `talon-agent tail --format pretty`.
+pub fn render_pretty(frame: &TailFrame) -> String {
+    let s = |v: Option<&Json>| v.and_then(|x| x.as_str()).unwrap_or("-").to_string();
+    let trace = frame.trace.as_ref();
+    let event = trace
+        .and_then(|t| t.get("event"))
+        .or_else(|| frame.raw.get("event"));
+    let tool = trace
+        .and_then(|t| t.pointer("/inputs/tool/name"))
+        .or_else(|| frame.raw.pointer("/payload/tool_name"))
+        .filter(|v| v.as_str().is_some_and(|s| !s.is_empty()));
+    let session = trace
+        .and_then(|t| t.pointer("/ids/session_id"))
+        .or_else(|| frame.raw.pointer("/env/session_id"))
+        .filter(|v| v.as_str().is_some_and(|s| !s.is_empty()));
+    let ts = trace
+        .and_then(|t| t.get("timestamp"))
+        .or_else(|| frame.raw.get("ts"));
+
+    let pretty = |v: &Json| serde_json::to_string_pretty(v).unwrap_or_default();
+    let mut out = format!(
+        "── {} {} tool={} session={}\n",
+        s(ts),
+        s(event),
+        s(tool),
+        s(session)
+    );
+    out.push_str(&format!("raw:\n{}\n", pretty(&frame.raw)));
+    if let Some(err) = &frame.error {
+        out.push_str(&format!("quarantined: {err}\n"));
+    }
+    if let Some(note) = &frame.note {
+        out.push_str(&format!("note: {note}\n"));
+    }
+    if let Some(t) = &frame.trace {
+        out.push_str(&format!("trace:\n{}\n", pretty(t)));
+    }
+    if let Some(o) = &frame.outgoing {
+        out.push_str(&format!("outgoing:\n{}\n", pretty(o)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_frame() -> Json {
+        serde_json::json!({
+            "event": "PostToolUse",
+            "env": { "session_id": "sess-1" },
+            "payload": { "tool_name": "Bash" }
+        })
+    }
+
+    #[test]
+    fn test_filter_matches_raw_and_canonical_names() {
+        let raw = raw_frame();
+        let mut trace = TraceV1 {
+            event: "tool.post".to_string(),
+            ..Default::default()
+        };
+        trace.ids.session_id = "sess-1".to_string();
+        trace.inputs.tool.name = "Bash".to_string();
+
+        let by_hook = TailFilter {
+            event: Some("PostToolUse".into()),
+            ..Default::default()
+        };
+        let by_canonical = TailFilter {
+            event: Some("tool.post".into()),
+            session_id: Some("sess-1".into()),
+            tool: Some("Bash".into()),
+        };
+        let other_tool = TailFilter {
+            tool: Some("Read".into()),
+            ..Default::default()
+        };
+
+        assert!(TailFilter::default().matches(&raw, None));
+        assert!(by_hook.matches(&raw, None));
+        assert!(by_canonical.matches(&raw, Some(&trace)));
+        assert!(
+            !by_canonical.matches(&raw, None),
+            "raw frame has no canonical name"
+        );
+        assert!(!other_tool.matches(&raw, Some(&trace)));
+    }
+
+    #[test]
+    fn test_hub_delivers_matching_frames_only() {
+        let hub = TailHub::default();
+        assert!(!hub.is_active());
+
+        let all = hub.subscribe(TailFilter::default());
+        let reads = hub.subscribe(TailFilter {
+            tool: Some("Read".into()),
+            ..Default::default()
+        });
+        assert!(hub.is_active());
+
+        let trace = TraceV1::default();
+        hub.publish_trace(&raw_frame(), &trace, &BeakOptions::default());
+        hub.publish_error(&serde_json::json!("not json"), "parse error");
+
+        let first = all
+            .try_recv()
+            .expect("unfiltered subscriber gets trace frame");
+        assert!(first.trace.is_some());
+        assert!(first.outgoing.is_some());
+        let second = all
+            .try_recv()
+            .expect("unfiltered subscriber gets error frame");
+        assert_eq!(second.error.as_deref(), Some("parse error"));
+        assert!(reads.try_recv().is_err(), "Read filter should see nothing");
+    }
+
+    #[test]
+    fn test_hub_drops_disconnected_subscribers() {
+        let hub = TailHub::default();
+        let rx = hub.subscribe(TailFilter::default());
+        drop(rx);
+
+        hub.publish_trace(&raw_frame(), &TraceV1::default(), &BeakOptions::default());
+        assert!(!hub.is_active());
+    }
+}
@@ -0,0 +1,682 @@
+//! Incremental transcript reader.
+//!
+//! Hook events carry the path of the session's transcript JSONL, which grows by
+//! one line per message for the whole session. Rescanning it on every event is
+//! O(session length), so the agent keeps one [`Transcript`] per path that
+//! remembers how far it has read and what it has seen so far, and only parses
+//! lines appended since the last event.
+//!
+//! A cached entry is reset and the file re-read from the start when the file is
+//! replaced (different inode), truncated (shorter than the saved offset) or
+//! rewritten in place (same length, newer mtime). Entries not used for
+//! [`IDLE_EVICT`] are dropped, and at most [`MAX_ENTRIES`] are kept.
+
+use crate::schema::{Message, ToolCall};
+use chrono::{DateTime, FixedOffset};
+use serde_json::Value as Json;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Entries unused for this long are evicted.
+const IDLE_EVICT: Duration = Duration::from_secs(30 * 60);
+
+/// Upper bound on cached transcripts; the least recently used go first.
+const MAX_ENTRIES: usize = 256;
+
+/// Messages kept for the current turn; older ones are dropped first.
+const MAX_TURN_MESSAGES: usize = 200;
+
+/// Characters kept per message text. Capture limits are applied later; this only
+/// bounds memory.
+const MAX_STORED_CHARS: usize = 64 * 1024;
+
+/// Recent assistant messages whose tool calls and timing are remembered.
+const MAX_RECENT_MESSAGES: usize = 64;
+
+/// Status of a tool call whose result hasn't been written yet.
+pub const TOOL_PENDING: &str = "pending";
+
+static CACHE: LazyLock<Mutex<TranscriptCache>> = LazyLock::new(Default::default);
+
+/// What has been learned from a transcript so far.
+#[derive(Debug, Default, Clone)]
+pub struct Transcript {
+    /// Latest assistant entry that reports usage.
+    pub latest_assistant: Option<Json>,
+
+    /// User prompt and assistant text messages since the latest user prompt.
+    pub turn: Vec<TurnMessage>,
+
+    /// Tool calls of recent assistant messages, oldest message first.
+    tool_calls: VecDeque<MessageTools>,
+
+    /// Write times of recent assistant messages, oldest first.
+    timings: VecDeque<MessageTiming>,
+
+    /// Time of the latest dialogue entry, i.e. when the next request was sent.
+    last_entry: Option<DateTime<FixedOffset>>,
+}
+
+/// When an assistant message was requested and written.
+#[derive(Debug, Clone)]
+struct MessageTiming {
+    id: String,
+    /// The user or tool_result entry (or previous message) it answers.
+    request: DateTime<FixedOffset>,
+    /// First and last content block entries.
+    first: DateTime<FixedOffset>,
+    last: DateTime<FixedOffset>,
+}
+
+/// Tool calls requested by one assistant message.
+#[derive(Debug, Clone)]
+struct MessageTools {
+    /// Anthropic message ID.
+    id: String,
+    /// `tool_use` block IDs, parallel to `calls`.
+    use_ids: Vec<String>,
+    calls: Vec<ToolCall>,
+}
+
+/// One text message of the current turn.
+#[derive(Debug, Clone)]
+pub struct TurnMessage {
+    /// Role, text and full length; `truncated` is set if the stored text was cut.
+    pub message: Message,
+    /// Anthropic message ID, used to join assistant entries split per content block.
+    id: String,
+}
+
+impl Transcript {
+    fn apply(&mut self, entry: Json) {
+        let kind = entry.get("type").and_then(|t| t.as_str());
+        let flag = |key: &str| entry.get(key).and_then(|v| v.as_bool()) == Some(true);
+        // Subagent and meta entries (command caveats etc.) aren't part of the dialogue.
+        if flag("isSidechain") || flag("isMeta") {
+            return;
+        }
+        let at = entry
+            .get("timestamp")
+            .and_then(|v| v.as_str())
+            .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok());
+        let is_dialogue = matches!(kind, Some("user" | "assistant"));
+        match kind {
+            Some("user") if is_prompt(&entry) => {
+                self.turn.clear();
+                self.push_text("user", "", message_text(&entry));
+            }
+            Some("user") => self.record_tool_results(&entry),
+            Some("assistant") => {
+                let id = entry
+                    .pointer("/message/id")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string();
+                self.push_text("assistant", &id, message_text(&entry));
+                self.record_tool_uses(&id, &entry);
+                if let Some(at) = at {
+                    self.record_timing(&id, at);
+                }
+                if entry
+                    .get("message")
+                    .is_some_and(|m| m.get("usage").is_some())
+                {
+                    self.latest_assistant = Some(entry);
+                }
+            }
+            _ => {}
+        }
+        if is_dialogue && at.is_some() {
+            self.last_entry = at;
+        }
+    }
+
+    /// Milliseconds from the request to an assistant message's first and last
+    /// content block being written: roughly time to first output and the full
+    /// model call.
+    pub fn latency(&self, message_id: &str) -> Option<(u32, u32)> {
+        let timing = self.timings.iter().rev().find(|m| m.id == message_id)?;
+        let ms = |to: DateTime<FixedOffset>| {
+            let ms = (to - timing.request).num_milliseconds().max(0) as u64;
+            ms.min(u64::from(u32::MAX)) as u32
+        };
+        Some((ms(timing.first), ms(timing.last)))
+    }
+
+    fn record_timing(&mut self, message_id: &str, at: DateTime<FixedOffset>) {
+        if message_id.is_empty() {
+            return;
+        }
+        if let Some(timing) = self.timings.back_mut()
+            && timing.id == message_id
+        {
+            timing.last = timing.last.max(at);
+            return;
+        }
+        let Some(request) = self.last_entry else {
+            return;
+        };
+        if self.timings.len() >= MAX_RECENT_MESSAGES {
+            self.timings.pop_front();
+        }
+        self.timings.push_back(MessageTiming {
+            id: message_id.to_string(),
+            request,
+            first: at,
+            last: at,
+        });
+    }
+
+    /// Tool calls requested by an assistant message, with their status so far.
+    ///
+    /// Calls without a result yet have status [`TOOL_PENDING`].
+    pub fn tool_calls(&self, message_id: &str) -> Vec<ToolCall> {
+        self.tool_calls
+            .iter()
+            .rev()
+            .find(|m| m.id == message_id)
+            .map(|m| m.calls.clone())
+            .unwrap_or_default()
+    }
+
+    fn record_tool_uses(&mut self, message_id: &str, entry: &Json) {
+        let uses: Vec<_> = content_blocks(entry, "tool_use").collect();
+        if message_id.is_empty() || uses.is_empty() {
+            return;
+        }
+        if self.tool_calls.back().is_none_or(|m| m.id != message_id) {
+            if self.tool_calls.len() >= MAX_RECENT_MESSAGES {
+                self.tool_calls.pop_front();
+            }
+            self.tool_calls.push_back(MessageTools {
+                id: message_id.to_string(),
+                use_ids: Vec::new(),
+                calls: Vec::new(),
+            });
+        }
+        let Some(message) = self.tool_calls.back_mut() else {
+            return;
+        };
+        for block in uses {
+            let str_field = |key: &str| {
+                block
+                    .get(key)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string()
+            };
+            message.use_ids.push(str_field("id"));
+            message.calls.push(ToolCall {
+                name: str_field("name"),
+                args: block.get("input").cloned().unwrap_or(Json::Null),
+                status: TOOL_PENDING.to_string(),
+            });
+        }
+    }
+
+    fn record_tool_results(&mut self, entry: &Json) {
+        for block in content_blocks(entry, "tool_result") {
+            let Some(use_id) = block.get("tool_use_id").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let is_error = block.get("is_error").and_then(|v| v.as_bool()) == Some(true);
+            // Results usually follow their call closely; search newest first.
+            let call = self.tool_calls.iter_mut().rev().find_map(|m| {
+                let i = m.use_ids.iter().position(|id| id == use_id)?;
+                m.calls.get_mut(i)
+            });
+            if let Some(call) = call {
+                call.status = if is_error { "error" } else { "success" }.to_string();
+            }
+        }
+    }
+
+    fn push_text(&mut self, role: &str, id: &str, text: String) {
+        if text.is_empty() {
+            return;
+        }
+        // Claude Code writes one entry per content block; join blocks of one message.
+        if let Some(last) = self.turn.last_mut()
+            && !id.is_empty()
+            && last.id == id
+        {
+            let msg = &mut last.message;
+            msg.chars = msg.chars.saturating_add(1 + count(&text));
+            msg.content.push('\n');
+            msg.content.push_str(&text);
+            msg.truncated |= truncate_chars(&mut msg.content, MAX_STORED_CHARS);
+            return;
+        }
+        let chars = count(&text);
+        let mut content = text;
+        let truncated = truncate_chars(&mut content, MAX_STORED_CHARS);
+        self.turn.push(TurnMessage {
+            message: Message {
+                role: role.to_string(),
+                content,
+                chars,
+                truncated,
+            },
+            id: id.to_string(),
+        });
+        if self.turn.len() > MAX_TURN_MESSAGES {
+            self.turn.remove(0);
+        }
+    }
+}
+
+fn count(s: &str) -> u32 {
+    s.chars().count().min(u32::MAX as usize) as u32
+}
+
+/// Whether a user entry is a typed prompt rather than a tool result.
+fn is_prompt(entry: &Json) -> bool {
+    match entry.pointer("/message/content") {
+        Some(Json::String(_)) => true,
+        Some(Json::Array(blocks)) => blocks
+            .iter()
+            .all(|b| b.get("type").and_then(|t| t.as_str()) != Some("tool_result")),
+        _ => false,
+    }
+}
+
+/// Content blocks of a transcript entry's message with the given `type`.
+fn content_blocks<'a>(entry: &'a Json, kind: &'a str) -> impl Iterator<Item = &'a Json> {
+    entry
+        .pointer("/message/content")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter(move |b| b.get("type").and_then(|t| t.as_str()) == Some(kind))
+}
+
+/// Joins the text blocks of a transcript entry's message content.
+///
+/// Content is either a plain string or an array of typed blocks; only `text`
+/// blocks are kept (tool_use and thinking blocks are skipped).
+pub fn message_text(entry: &Json) -> String {
+    match entry.get("message").and_then(|m| m.get("content")) {
+        Some(Json::String(s)) => s.clone(),
+        Some(Json::Array(blocks)) => blocks
+            .iter()
+            .filter(|b| b.get("type").and_then(|t| t.as_str()) == Some("text"))
+            .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Cuts `s` to at most `max` characters. Returns whether anything was removed.
+pub fn truncate_chars(s: &mut String, max: usize) -> bool {
+    match s.char_indices().nth(max) {
+        Some((at, _)) => {
+            s.truncate(at);
+            true
+        }
+        None => false,
+    }
+}
+
+/// File identity used to detect replacement and in-place rewrites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileId {
+    inode: u64,
+    mtime: Option<SystemTime>,
+}
+
+struct Entry {
+    id: FileId,
+    /// Bytes consumed so far; always at a line boundary.
+    offset: u64,
+    state: Transcript,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct TranscriptCache {
+    entries: HashMap<PathBuf, Entry>,
+}
+
+/// Brings the cached view of `path` up to date and passes it to `f`.
+///
+/// Returns `None` if the file can't be opened.
+pub fn read<R>(path: &Path, f: impl FnOnce(&Transcript) -> R) -> Option<R> {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.read(path, Instant::now()).map(f)
+}
+
+impl TranscriptCache {
+    fn read(&mut self, path: &Path, now: Instant) -> Option<&Transcript> {
+        self.evict(now);
+
+        let mut file = match File::open(path) {
+            Ok(f) => f,
+            Err(_) => {
+                self.entries.remove(path);
+                return None;
+            }
+        };
+        let meta = file.metadata().ok()?;
+        let id = FileId {
+            inode: inode(&meta),
+            mtime: meta.modified().ok(),
+        };
+        let len = meta.len();
+
+        let entry = self.entries.entry(path.to_path_buf()).or_insert(Entry {
+            id,
+            offset: 0,
+            state: Transcript::default(),
+            last_used: now,
+        });
+        entry.last_used = now;
+
+        let replaced = entry.id.inode != id.inode;
+        let truncated = len < entry.offset;
+        let rewritten = len == entry.offset && entry.id.mtime != id.mtime && entry.offset > 0;
+        if replaced || truncated || rewritten {
+            entry.offset = 0;
+            entry.state = Transcript::default();
+        }
+        entry.id = id;
+
+        if len > entry.offset {
+            file.seek(SeekFrom::Start(entry.offset)).ok()?;
+            let mut buf = Vec::with_capacity((len - entry.offset) as usize);
+            // Read only up to the length we stat'ed; later appends wait for the next event.
+            file.take(len - entry.offset).read_to_end(&mut buf).ok()?;
+            entry.offset += consume(&buf, &mut entry.state);
+        }
+
+        Some(&entry.state)
+    }
+
+    fn evict(&mut self, now: Instant) {
+        self.entries
+            .retain(|_, e| now.saturating_duration_since(e.last_used) < IDLE_EVICT);
+        while self.entries.len() >= MAX_ENTRIES {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(p, _)| p.clone())
+            else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+/// Applies every complete line in `buf` and returns the number of bytes consumed.
+///
+/// A trailing line without a newline is consumed only if it already parses,
+/// since the writer may still be in the middle of it.
+fn consume(buf: &[u8], state: &mut Transcript) -> u64 {
+    let mut used = 0;
+    for line in buf.split_inclusive(|&b| b == b'\n') {
+        let complete = line.ends_with(b"\n");
+        let parsed = serde_json::from_slice::<Json>(line.trim_ascii());
+        if !complete && parsed.is_err() {
+            break;
+        }
+        // Malformed complete lines are skipped, as a full rescan would.
+        if let Ok(entry) = parsed {
+            state.apply(entry);
+        }
+        used += line.len();
+    }
+    used as u64
+}
+
+#[cfg(unix)]
+fn inode(meta: &std::fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(meta)
+}
+
+#[cfg(not(unix))]
+fn inode(_meta: &std::fs::Metadata) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn assistant(id: &str) -> String {
+        format!(r#"{{"type":"assistant","message":{{"id":"{id}","usage":{{"input_tokens":1}}}}}}"#)
+    }
+
+    fn latest_id(t: &Transcript) -> Option<String> {
+        t.latest_assistant
+            .as_ref()
+            .and_then(|e| e.pointer("/message/id"))
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        f.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_reads_only_appended_lines() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("t.jsonl");
+        append(
+            &path,
+            &format!("{}\n{{\"type\":\"user\"}}\n", assistant("m1")),
+        );
+
+        let mut cache = TranscriptCache::default();
+        let now = Instant::now();
+        assert_eq!(
+            latest_id(cache.read(&path, now).unwrap()).as_deref(),
+            Some("m1")
+        );
+        let offset = cache.entries[&path].offset;
+        assert_eq!(offset, std::fs::metadata(&path).unwrap().len());
+
+        append(&path, &format!("{}\n", assistant("m2")));
+        assert_eq!(
+            latest_id(cache.read(&path, now).unwrap()).as_deref(),
+            Some("m2")
+        );
+        assert!(cache.entries[&path].offset > offset);
+    }
+
+    #[test]
+    fn test_partial_line_waits_for_the_rest() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("t.jsonl");
+        let line = assistant("m1");
+        let (head, tail) = line.split_at(20);
+        append(&path, head);
+
+        let mut cache = TranscriptCache::default();
+        let now = Instant::now();
+        assert_eq!(latest_id(cache.read(&path, now).unwrap()), None);
+        assert_eq!(cache.entries[&path].offset, 0);
+
+        append(&path, &format!("{tail}\n"));
+        assert_eq!(
+            latest_id(cache.read(&path, now).unwrap()).as_deref(),
+            Some("m1")
+        );
+    }
+
+    #[test]
+    fn test_truncation_and_replacement_reset_the_entry() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("t.jsonl");
+        append(
+            &path,
+            &format!("{}\n{}\n", assistant("m1"), assistant("m2")),
+        );
+
+        let mut cache = TranscriptCache::default();
+        let now = Instant::now();
+        cache.read(&path, now).unwrap();
+
+        // Truncated and restarted.
+        std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        append(&path, &format!("{}\n", assistant("m3")));
+        assert_eq!(
+            latest_id(cache.read(&path, now).unwrap()).as_deref(),
+            Some("m3")
+        );
+
+        // Replaced by a different file (rename over it).
+        let other = temp.path().join("new.jsonl");
+        append(
+            &other,
+            &format!(
+                "{}\n{}\n{}\n",
+                assistant("a"),
+                assistant("b"),
+                assistant("m4")
+            ),
+        );
+        std::fs::rename(&other, &path).unwrap();
+        assert_eq!(
+            latest_id(cache.read(&path, now).unwrap()).as_deref(),
+            Some("m4")
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(cache.read(&path, now).is_none());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn test_turn_messages_start_at_the_latest_prompt() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("t.jsonl");
+        let lines = [
+            r#"{"type":"user","message":{"role":"user","content":"first question"}}"#,
+            r#"{"type":"assistant","message":{"id":"a0","content":[{"type":"text","text":"old answer"}]}}"#,
+            r#"{"type":"user","message":{"role":"user","content":[{"type":"text","text":"fix the bug"}]}}"#,
+            r#"{"type":"user","isMeta":true,"message":{"role":"user","content":"<caveat>"}}"#,
+            r#"{"type":"assistant","message":{"id":"a1","content":[{"type":"text","text":"Looking."}]}}"#,
+            r#"{"type":"assistant","message":{"id":"a1","content":[{"type":"tool_use","name":"Read"}]}}"#,
+            r#"{"type":"user","message":{"role":"user","content":[{"type":"tool_result","content":"..."}]}}"#,
+            r#"{"type":"assistant","isSidechain":true,"message":{"id":"s1","content":"subagent text"}}"#,
+            r#"{"type":"assistant","message":{"id":"a2","content":[{"type":"text","text":"Fixed."}]}}"#,
+            r#"{"type":"assistant","message":{"id":"a2","content":[{"type":"text","text":"Done."}]}}"#,
+        ];
+        append(&path, &(lines.join("\n") + "\n"));
+
+        let mut cache = TranscriptCache::default();
+        let t = cache.read(&path, Instant::now()).unwrap();
+        let got: Vec<_> = t
+            .turn
+            .iter()
+            .map(|m| {
+                (
+                    m.message.role.as_str(),
+                    m.message.content.as_str(),
+                    m.message.chars,
+                )
+            })
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("user", "fix the bug", 11),
+                ("assistant", "Looking.", 8),
+                ("assistant", "Fixed.\nDone.", 12),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tool_calls_are_matched_with_results() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("t.jsonl");
+        let lines = [
+            r#"{"type":"assistant","message":{"id":"a1","content":[{"type":"text","text":"Checking."}]}}"#,
+            r#"{"type":"assistant","message":{"id":"a1","content":[{"type":"tool_use","id":"tu1","name":"Read","input":{"file_path":"/x"}}]}}"#,
+            r#"{"type":"assistant","message":{"id":"a1","content":[{"type":"tool_use","id":"tu2","name":"Bash","input":{"command":"false"}}]}}"#,
+            r#"{"type":"assistant","message":{"id":"a1","content":[{"type":"tool_use","id":"tu3","name":"Grep","input":{}}]}}"#,
+            r#"{"type":"user","message":{"role":"user","content":[{"type":"tool_result","tool_use_id":"tu1","content":"..."}]}}"#,
+            r#"{"type":"user","message":{"role":"user","content":[{"type":"tool_result","tool_use_id":"tu2","is_error":true,"content":"exit 1"}]}}"#,
+        ];
+        append(&path, &(lines.join("\n") + "\n"));
+
+        let mut cache = TranscriptCache::default();
+        let t = cache.read(&path, Instant::now()).unwrap();
+        let got: Vec<_> = t
+            .tool_calls("a1")
+            .into_iter()
+            .map(|c| (c.name, c.status))
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("Read".to_string(), "success".to_string()),
+                ("Bash".to_string(), "error".to_string()),
+                ("Grep".to_string(), TOOL_PENDING.to_string()),
+            ]
+        );
+        assert_eq!(t.tool_calls("a1")[0].args["file_path"], "/x");
+        assert!(t.tool_calls("a2").is_empty());
+    }
+
+    #[test]
+    fn test_latency_runs_from_request_to_last_block() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("t.jsonl");
+        let lines = [
+            r#"{"type":"user","timestamp":"2025-11-13T10:00:00.000Z","message":{"role":"user","content":"hi"}}"#,
+            r#"{"type":"assistant","timestamp":"2025-11-13T10:00:01.200Z","message":{"id":"a1","content":[{"type":"text","text":"Let me look."}]}}"#,
+            r#"{"type":"assistant","timestamp":"2025-11-13T10:00:03.000Z","message":{"id":"a1","content":[{"type":"tool_use","id":"tu1","name":"Read","input":{}}]}}"#,
+            r#"{"type":"user","timestamp":"2025-11-13T10:00:03.500Z","message":{"role":"user","content":[{"type":"tool_result","tool_use_id":"tu1"}]}}"#,
+            r#"{"type":"assistant","isSidechain":true,"timestamp":"2025-11-13T10:00:04.000Z","message":{"id":"s1","content":"x"}}"#,
+            r#"{"type":"assistant","timestamp":"2025-11-13T10:00:05.500Z","message":{"id":"a2","content":[{"type":"text","text":"Done."}]}}"#,
+        ];
+        append(&path, &(lines.join("\n") + "\n"));
+
+        let mut cache = TranscriptCache::default();
+        let t = cache.read(&path, Instant::now()).unwrap();
+        assert_eq!(t.latency("a1"), Some((1200, 3000)));
+        assert_eq!(t.latency("a2"), Some((2000, 2000)));
+        assert_eq!(t.latency("s1"), None);
+    }
+
+    #[test]
+    fn test_idle_entries_are_evicted() {
+        let temp = TempDir::new().unwrap();
+        let a = temp.path().join("a.jsonl");
+        let b = temp.path().join("b.jsonl");
+        append(&a, &format!("{}\n", assistant("m1")));
+        append(&b, &format!("{}\n", assistant("m2")));
+
+        let mut cache = TranscriptCache::default();
+        let start = Instant::now();
+        cache.read(&a, start).unwrap();
+        cache.read(&b, start + IDLE_EVICT / 2).unwrap();
+        cache
+            .read(&b, start + IDLE_EVICT + Duration::from_secs(1))
+            .unwrap();
+        assert!(!cache.entries.contains_key(&a));
+        assert!(cache.entries.contains_key(&b));
+    }
+}
@@ -0,0 +1,239 @@
+//! Attributes transcript token usage to exactly one event per assistant message.
+//!
+//! Every hook event is enriched with the usage of the latest assistant message in
+//! the transcript, so all tool calls made from one message carry the same token
+//! counts and summing `total_tokens` over events over-counts. The ledger remembers,
+//! per session, how much of each message's usage has already been sent:
+//!
+//! - the first event carrying a message keeps its usage (`usage_attribution = first`),
+//! - a later event whose counts grew (the message was still streaming) carries only
+//!   the increase (`delta`),
+//! - any other event carrying the message has its token counts zeroed (`repeat`).
+//!
+//! Sums over several messages, such as a turn's totals, are carried in labels
+//! rather than metrics so they don't interfere with this.
+
+use crate::schema::TraceV1;
+use std::collections::{HashMap, VecDeque};
+
+/// Label recording how an event's token counts relate to its message.
+pub const USAGE_ATTRIBUTION: &str = "usage_attribution";
+
+/// Sessions tracked at once; the least recently active is forgotten first.
+const MAX_SESSIONS: usize = 1024;
+
+/// Recent messages remembered per session. Events carry the latest message, so
+/// older ones don't come back.
+const MAX_RECENT_MESSAGES: usize = 64;
+
+/// Token counts of one message: prompt, completion, cache creation, cache read.
+type Tokens = [u32; 4];
+
+#[derive(Default)]
+struct Session {
+    /// Counts already attributed to recent messages, oldest first.
+    messages: VecDeque<(String, Tokens)>,
+    last_seen: u64,
+}
+
+/// Per-session record of attributed message usage.
+#[derive(Default)]
+pub struct UsageLedger {
+    sessions: HashMap<String, Session>,
+    /// Monotonic counter used to order sessions by recency.
+    clock: u64,
+}
+
+impl UsageLedger {
+    /// Adjusts `t`'s token counts so each message's usage is counted once.
+    pub fn attribute(&mut self, t: &mut TraceV1) {
+        let session = t.ids.session_id.clone();
+        if t.event == "session.end" {
+            self.sessions.remove(&session);
+        }
+        // Already attributed (e.g. replayed from an upstream agent).
+        if t.ids.message_id.is_empty() || t.label(USAGE_ATTRIBUTION).is_some() {
+            return;
+        }
+        let m = &t.metrics;
+        let current: Tokens = [
+            m.prompt_tokens,
+            m.completion_tokens,
+            m.cache_creation_input_tokens,
+            m.cache_read_input_tokens,
+        ];
+        if current == [0; 4] && m.total_tokens == 0 {
+            return;
+        }
+
+        self.clock += 1;
+        if !self.sessions.contains_key(&session) && self.sessions.len() >= MAX_SESSIONS {
+            self.forget_oldest();
+        }
+        let entry = self.sessions.entry(session).or_default();
+        entry.last_seen = self.clock;
+
+        let sent = entry
+            .messages
+            .iter_mut()
+            .find(|(id, _)| *id == t.ids.message_id)
+            .map(|(_, sent)| sent);
+        let (label, tokens) = match sent {
+            None => {
+                if entry.messages.len() >= MAX_RECENT_MESSAGES {
+                    entry.messages.pop_front();
+                }
+                entry
+                    .messages
+                    .push_back((t.ids.message_id.clone(), current));
+                ("first", current)
+            }
+            Some(sent) if current.iter().zip(sent.iter()).any(|(c, s)| c > s) => {
+                let mut delta = [0; 4];
+                for i in 0..4 {
+                    delta[i] = current[i].saturating_sub(sent[i]);
+                    sent[i] = sent[i].max(current[i]);
+                }
+                ("delta", delta)
+            }
+            Some(_) => ("repeat", [0; 4]),
+        };
+        if label != "first" {
+            set_tokens(t, tokens);
+        }
+        t.set_label(USAGE_ATTRIBUTION, label);
+    }
+
+    fn forget_oldest(&mut self) {
+        if let Some(oldest) = self
+            .sessions
+            .iter()
+            .min_by_key(|(_, s)| s.last_seen)
+            .map(|(k, _)| k.clone())
+        {
+            self.sessions.remove(&oldest);
+        }
+    }
+}
+
+fn set_tokens(t: &mut TraceV1, [prompt, completion, creation, read]: Tokens) {
+    let total = prompt.saturating_add(completion);
+    t.metrics.prompt_tokens = prompt;
+    t.metrics.completion_tokens = completion;
+    t.metrics.total_tokens = total;
+    t.metrics.cache_creation_input_tokens = creation;
+    t.metrics.cache_read_input_tokens = read;
+    t.metrics.uncached_input_tokens = prompt.saturating_sub(creation).saturating_sub(read);
+    t.outputs.input_tokens = prompt;
+    t.outputs.output_tokens = completion;
+    t.outputs.total_tokens = total;
+    t.metrics.input_cost_usd = 0.0;
+    t.metrics.output_cost_usd = 0.0;
+    t.metrics.total_cost_usd = 0.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(session: &str, msg: &str, prompt: u32, completion: u32) -> TraceV1 {
+        let mut t = TraceV1 {
+            event: "tool.post".to_string(),
+            ..Default::default()
+        };
+        t.ids.session_id = session.to_string();
+        t.ids.message_id = msg.to_string();
+        t.metrics.prompt_tokens = prompt;
+        t.metrics.completion_tokens = completion;
+        t.metrics.total_tokens = prompt + completion;
+        t
+    }
+
+    #[test]
+    fn test_usage_is_counted_once_per_message() {
+        let mut ledger = UsageLedger::default();
+        let mut sent = 0;
+        let mut labels = Vec::new();
+        for mut t in [
+            event("s", "m1", 100, 10),
+            event("s", "m1", 100, 10),
+            event("s", "m1", 100, 25),
+            event("s", "m2", 200, 5),
+            event("other", "m1", 100, 10),
+        ] {
+            ledger.attribute(&mut t);
+            sent += t.metrics.total_tokens;
+            labels.push(t.label(USAGE_ATTRIBUTION).unwrap().to_string());
+        }
+        assert_eq!(sent, 125 + 205 + 110);
+        assert_eq!(labels, ["first", "repeat", "delta", "first", "first"]);
+    }
+
+    #[test]
+    fn test_cache_breakdown_follows_attribution() {
+        let mut ledger = UsageLedger::default();
+        let cached = |prompt, read| {
+            let mut t = event("s", "m1", prompt, 10);
+            t.metrics.cache_read_input_tokens = read;
+            t.metrics.uncached_input_tokens = prompt - read;
+            t
+        };
+        let mut first = cached(100, 80);
+        ledger.attribute(&mut first);
+        assert_eq!(first.metrics.cache_read_input_tokens, 80);
+
+        let mut delta = cached(150, 120);
+        ledger.attribute(&mut delta);
+        assert_eq!(delta.metrics.prompt_tokens, 50);
+        assert_eq!(delta.metrics.cache_read_input_tokens, 40);
+        assert_eq!(delta.metrics.uncached_input_tokens, 10);
+
+        let mut repeat = cached(150, 120);
+        ledger.attribute(&mut repeat);
+        assert_eq!(repeat.metrics.cache_read_input_tokens, 0);
+        assert_eq!(repeat.metrics.uncached_input_tokens, 0);
+    }
+
+    #[test]
+    fn test_attributed_and_message_less_events_are_untouched() {
+        let mut ledger = UsageLedger::default();
+        let mut first = event("s", "m1", 100, 10);
+        ledger.attribute(&mut first);
+
+        let mut replayed = event("s", "m1", 100, 10);
+        replayed.set_label(USAGE_ATTRIBUTION, "first");
+        ledger.attribute(&mut replayed);
+        assert_eq!(replayed.metrics.total_tokens, 110);
+
+        let mut bare = event("s", "", 5, 5);
+        ledger.attribute(&mut bare);
+        assert_eq!(bare.metrics.total_tokens, 10);
+        assert_eq!(bare.label(USAGE_ATTRIBUTION), None);
+    }
+
+    #[test]
+    fn test_only_recent_messages_are_remembered() {
+        let mut ledger = UsageLedger::default();
+        for i in 0..MAX_RECENT_MESSAGES + 10 {
+            ledger.attribute(&mut event("s", &format!("m{i}"), 1, 1));
+        }
+        let messages = &ledger.sessions["s"].messages;
+        assert_eq!(messages.len(), MAX_RECENT_MESSAGES);
+        assert_eq!(messages.front().unwrap().0, "m10");
+
+        let mut latest = event("s", &format!("m{}", MAX_RECENT_MESSAGES + 9), 1, 1);
+        ledger.attribute(&mut latest);
+        assert_eq!(latest.label(USAGE_ATTRIBUTION), Some("repeat"));
+    }
+
+    #[test]
+    fn test_session_end_forgets_the_session() {
+        let mut ledger = UsageLedger::default();
+        ledger.attribute(&mut event("s", "m1", 1, 1));
+
+        let mut end = event("s", "", 0, 0);
+        end.event = "session.end".to_string();
+        ledger.attribute(&mut end);
+        assert!(ledger.sessions.is_empty());
+    }
+}
@@ -92,9 +92,11 @@ fn try_send(_ipc_path: &str, payload: &[u8]) -> io::Result<()> {
 /// falling back to `PATH` lookup if not set. This allows installations in
 /// non-standard locations or testing with local builds.
 ///
-/// Configuration is passed through environment variables rather than a config file
-/// to support containerized and ephemeral environments where file-based config
-/// would be more complex to manage.
+/// Only the endpoint and socket are passed as flags. Secrets never go on argv,
+/// where any local user could read them with `ps`: the agent inherits this
+/// process's environment, so `TRACE_API_KEY` (or `TALON_API_KEY_FILE` /
+/// `TALON_API_KEY_CMD`), `TALON_*` settings and `TALON_CONFIG`/`TALON_PROFILE` reach
+/// it that way, and it reads the default config file on its own.
 ///
 /// # Arguments
 ///
@@ -120,9 +122,6 @@ fn start_agent(ipc_path: &str) -> io::Result<()> {
     if let Ok(endpoint) = env::var("TRACE_ENDPOINT") {
         cmd.arg("--endpoint").arg(endpoint);
     }
-    if let Ok(key) = env::var("TRACE_API_KEY") {
-        cmd.arg("--api-key").arg(key);
-    }
     if !ipc_path.is_empty() {
         cmd.arg("--sock").arg(ipc_path);
     }